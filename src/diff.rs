@@ -0,0 +1,128 @@
+use crate::Finding;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// What changed on one host between two scans
+#[derive(Serialize, Default, Debug)]
+pub struct HostDiff {
+    pub host: String,
+    /// Ports open now that were not open before
+    pub opened: Vec<PortRef>,
+    /// Ports open before that are no longer open
+    pub closed: Vec<PortRef>,
+    /// Ports open in both scans whose banner differs
+    pub changed: Vec<BannerChange>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PortRef {
+    pub port: u16,
+    pub protocol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BannerChange {
+    pub port: u16,
+    pub protocol: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+// Both the full JSON report and a bare list of findings are accepted
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedScan {
+    Report { findings: Vec<Finding> },
+    Findings(Vec<Finding>),
+}
+
+/// Read findings back from `--output-format json` or `jsonl` output
+pub fn load_findings(path: &Path) -> anyhow::Result<Vec<Finding>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Could not read scan results {}: {}", path.display(), e))?;
+    if let Ok(saved) = serde_json::from_str::<SavedScan>(&content) {
+        return Ok(match saved {
+            SavedScan::Report { findings } | SavedScan::Findings(findings) => findings,
+        });
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("Invalid scan results {}:{}: {}", path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+/// Compare the open ports of two scans, keyed by host, port and protocol.
+/// Banners are compared after [`crate::normalize_banner`], so a new `Date:` header is
+/// not a change. Hosts without changes are left out; the result is sorted by host.
+pub fn diff(old: &[Finding], new: &[Finding]) -> Vec<HostDiff> {
+    fn open(findings: &[Finding]) -> HashMap<(Arc<str>, u16, String), &Finding> {
+        findings
+            .iter()
+            .filter(|f| f.status == "open")
+            .map(|f| ((f.host.clone(), f.port, f.protocol.clone()), f))
+            .collect()
+    }
+    let (old, new) = (open(old), open(new));
+
+    let mut hosts: BTreeMap<String, HostDiff> = BTreeMap::new();
+    for ((host, port, protocol), finding) in &new {
+        let key = (host.clone(), *port, protocol.clone());
+        match old.get(&key) {
+            None => {
+                let opened = PortRef { port: *port, protocol: protocol.clone(), banner: finding.banner.clone() };
+                entry(&mut hosts, host).opened.push(opened);
+            }
+            Some(old_finding) if !same_banner(old_finding, finding) => {
+                let changed = BannerChange {
+                    port: *port,
+                    protocol: protocol.clone(),
+                    old: old_finding.banner.clone(),
+                    new: finding.banner.clone(),
+                };
+                entry(&mut hosts, host).changed.push(changed);
+            }
+            Some(_) => {}
+        }
+    }
+    for ((host, port, protocol), finding) in &old {
+        if !new.contains_key(&(host.clone(), *port, protocol.clone())) {
+            let closed = PortRef { port: *port, protocol: protocol.clone(), banner: finding.banner.clone() };
+            entry(&mut hosts, host).closed.push(closed);
+        }
+    }
+
+    let mut hosts: Vec<HostDiff> = hosts.into_values().collect();
+    for host in &mut hosts {
+        host.opened.sort_by(|a, b| (a.port, &a.protocol).cmp(&(b.port, &b.protocol)));
+        host.closed.sort_by(|a, b| (a.port, &a.protocol).cmp(&(b.port, &b.protocol)));
+        host.changed.sort_by(|a, b| (a.port, &a.protocol).cmp(&(b.port, &b.protocol)));
+    }
+    hosts
+}
+
+// Scans saved before banners were normalized only have the cleaned banner,
+// which cannot be split into lines again, so those are compared as they are
+fn same_banner(old: &Finding, new: &Finding) -> bool {
+    let normalized = |f: &Finding| f.banner.is_none() || f.banner_normalized.is_some();
+    if normalized(old) && normalized(new) {
+        old.banner_normalized == new.banner_normalized
+    } else {
+        old.banner == new.banner
+    }
+}
+
+fn entry<'a>(hosts: &'a mut BTreeMap<String, HostDiff>, host: &str) -> &'a mut HostDiff {
+    hosts
+        .entry(host.to_string())
+        .or_insert_with(|| HostDiff { host: host.to_string(), ..Default::default() })
+}
@@ -0,0 +1,55 @@
+use crate::IpFamily;
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfig, ResolveHosts, ResolverConfig};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::TokioResolver;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Resolves names against one DNS server instead of the system resolver.
+/// The address family picks the records asked for: A for IPv4, AAAA for IPv6, both otherwise.
+#[derive(Clone)]
+pub struct DnsResolver {
+    server: IpAddr,
+    family: IpFamily,
+    inner: TokioResolver,
+}
+
+impl DnsResolver {
+    pub fn new(server: IpAddr, family: IpFamily) -> anyhow::Result<Self> {
+        let mut config = ResolverConfig::default();
+        config.add_name_server(NameServerConfig::udp_and_tcp(server));
+        let mut builder = TokioResolver::builder_with_config(config, TokioRuntimeProvider::default());
+        let options = builder.options_mut();
+        options.ip_strategy = match family {
+            IpFamily::Any => LookupIpStrategy::Ipv4AndIpv6,
+            IpFamily::V4 => LookupIpStrategy::Ipv4Only,
+            IpFamily::V6 => LookupIpStrategy::Ipv6Only,
+        };
+        // /etc/hosts belongs to the local view of the network the server is meant to bypass
+        options.use_hosts_file = ResolveHosts::Never;
+        let inner = builder
+            .build()
+            .map_err(|e| anyhow::anyhow!("Could not set up DNS server {}: {}", server, e))?;
+        Ok(DnsResolver { server, family, inner })
+    }
+
+    /// The server asked and the records asked for, which together decide the answer
+    pub(crate) fn source(&self) -> (IpAddr, IpFamily) {
+        (self.server, self.family)
+    }
+
+    pub async fn lookup(&self, host: &str) -> anyhow::Result<Vec<IpAddr>> {
+        let answer = self
+            .inner
+            .lookup_ip(host)
+            .await
+            .map_err(|e| anyhow::anyhow!("{} could not resolve {}: {}", self.server, host, e))?;
+        Ok(answer.iter().collect())
+    }
+}
+
+impl fmt::Debug for DnsResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsResolver").field("server", &self.server).finish()
+    }
+}
@@ -0,0 +1,79 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// A banner pattern naming the service it identifies. The first capture
+/// group, when present, is taken as the version.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub service: String,
+    pub pattern: Regex,
+}
+
+impl Rule {
+    pub fn new(service: &str, pattern: &str) -> Result<Rule, regex::Error> {
+        Ok(Rule {
+            service: service.to_string(),
+            pattern: Regex::new(pattern)?,
+        })
+    }
+}
+
+/// Service name and version extracted from a banner
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub service: String,
+    pub version: Option<String>,
+}
+
+// Banners are cleaned before matching, so CR/LF show up as '.' and versions
+// must not swallow trailing dots
+const VERSION: &str = r"(\d+(?:\.\d+)*[a-z0-9]*)";
+
+static BUILTIN: &[(&str, &str)] = &[
+    ("OpenSSH", r"SSH-[\d.]+-OpenSSH_(\d+(?:\.\d+)*(?:p\d+)?)"),
+    ("Dropbear", r"SSH-[\d.]+-dropbear_(\d+(?:\.\d+)*)"),
+    ("Apache", r"Server: Apache/{V}"),
+    ("nginx", r"Server: nginx/{V}"),
+    ("Microsoft-IIS", r"Server: Microsoft-IIS/{V}"),
+    ("lighttpd", r"Server: lighttpd/{V}"),
+    ("SimpleHTTP", r"Server: SimpleHTTP/{V}"),
+    ("vsftpd", r"vsFTPd {V}"),
+    ("ProFTPD", r"ProFTPD {V}"),
+    ("Pure-FTPd", r"Pure-FTPd"),
+    ("FileZilla", r"FileZilla Server(?: version)? {V}"),
+    ("Postfix", r"ESMTP Postfix"),
+    ("Exim", r"ESMTP Exim {V}"),
+    ("Sendmail", r"Sendmail {V}"),
+    ("Dovecot", r"Dovecot"),
+    ("MariaDB", r"{V}-MariaDB"),
+    ("Redis", r"redis_version:{V}"),
+];
+
+/// The rules ClapScan ships with
+pub fn builtin_rules() -> &'static [Rule] {
+    static RULES: OnceLock<Vec<Rule>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        BUILTIN
+            .iter()
+            .map(|(service, pattern)| {
+                Rule::new(service, &pattern.replace("{V}", VERSION)).expect("built-in rules are valid regexes")
+            })
+            .collect()
+    })
+}
+
+/// Identify a banner with the built-in rules
+pub fn fingerprint(banner: &str) -> Option<Fingerprint> {
+    fingerprint_with(banner, builtin_rules())
+}
+
+/// Identify a banner with a custom rule set, first match wins
+pub fn fingerprint_with(banner: &str, rules: &[Rule]) -> Option<Fingerprint> {
+    rules.iter().find_map(|rule| {
+        let caps = rule.pattern.captures(banner)?;
+        Some(Fingerprint {
+            service: rule.service.clone(),
+            version: caps.get(1).map(|m| m.as_str().to_string()),
+        })
+    })
+}
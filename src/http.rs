@@ -0,0 +1,177 @@
+use base64::Engine;
+use std::io;
+use std::sync::Once;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant};
+use tracing::warn;
+
+/// Status line and page title of an HTTP response
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpInfo {
+    pub status: Option<u16>,
+    pub title: Option<String>,
+}
+
+// Titles sit near the top of the page, no need to download whole sites
+const BODY_LIMIT: usize = 64 * 1024;
+// Longest title kept, some pages stuff whole paragraphs in there
+const TITLE_LIMIT: usize = 256;
+
+/// Send `GET /` over `stream` and read until the title shows up, the body
+/// limit is hit or `window` runs out. Partial responses are parsed as far as they go.
+pub(crate) async fn fetch(mut stream: TcpStream, host: &str, window: Duration) -> Option<HttpInfo> {
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nUser-Agent: clapscan\r\nAccept: text/html\r\nConnection: close\r\n\r\n",
+        host
+    );
+    stream.write_all(request.as_bytes()).await.ok()?;
+
+    let deadline = Instant::now() + window;
+    let mut response = Vec::new();
+    let mut buf = [0u8; 4096];
+    while response.len() < BODY_LIMIT {
+        match time::timeout_at(deadline, stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Ok(Err(_)) | Err(_) => break,
+            Ok(Ok(n)) => response.extend_from_slice(&buf[..n]),
+        }
+        if contains_ignore_case(&response, b"</title") {
+            break;
+        }
+    }
+    parse_response(&response)
+}
+
+// Longest CONNECT response head accepted from a proxy
+const CONNECT_HEAD_LIMIT: usize = 8 * 1024;
+
+/// Ask an HTTP proxy on `stream` to open a tunnel to `target` ("host:port"),
+/// returning the stream once the proxy answered 200. Refusals surface as
+/// `ConnectionRefused` so the port reads as closed.
+pub(crate) async fn connect_tunnel(
+    mut stream: TcpStream,
+    target: &str,
+    auth: Option<&(String, String)>,
+) -> io::Result<TcpStream> {
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: clapscan\r\n", target, target);
+    if let Some((user, pass)) = auth {
+        let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // One byte at a time, anything after the blank line already belongs to the target
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= CONNECT_HEAD_LIMIT || stream.read(&mut byte).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete CONNECT response from proxy"));
+        }
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status = head.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(stream),
+        // What proxies answer when the target refused the connection
+        Some(502 | 503) => Err(io::Error::from(io::ErrorKind::ConnectionRefused)),
+        Some(407) => {
+            // Every probe will fail the same way, once is enough to explain the filtered ports
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("The proxy rejected its credentials (407), check the user and password in --proxy"));
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "proxy authentication required"))
+        }
+        _ => Err(io::Error::other(format!("proxy refused CONNECT: {}", head.lines().next().unwrap_or_default()))),
+    }
+}
+
+fn parse_response(response: &[u8]) -> Option<HttpInfo> {
+    let text = String::from_utf8_lossy(response);
+    let status_line = text.lines().next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.split_whitespace().nth(1).and_then(|code| code.parse().ok());
+
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((&text, ""));
+    let chunked = head
+        .lines()
+        .any(|line| line.to_ascii_lowercase().replace(' ', "") == "transfer-encoding:chunked");
+    let body = if chunked { dechunk(body) } else { body.to_string() };
+    Some(HttpInfo { status, title: extract_title(&body) })
+}
+
+// Join chunk payloads, stopping quietly at a truncated chunk
+fn dechunk(body: &str) -> String {
+    let mut out = String::new();
+    let mut rest = body;
+    while let Some((size, after)) = rest.split_once("\r\n") {
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else { break };
+        if size == 0 {
+            break;
+        }
+        let end = size.min(after.len());
+        let Some(chunk) = after.get(..end) else { break };
+        out.push_str(chunk);
+        rest = after.get(end..).unwrap_or_default().trim_start_matches("\r\n");
+    }
+    out
+}
+
+fn extract_title(body: &str) -> Option<String> {
+    let lower = body.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    // A title cut off by the read limit is still worth reporting
+    let end = lower[start..].find("</title").map_or(body.len(), |i| start + i);
+    let title = decode_entities(&body[start..end]);
+    let title: String = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title: String = title.chars().take(TITLE_LIMIT).collect();
+    if title.is_empty() { None } else { Some(title) }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&semi| semi <= 10).and_then(|semi| {
+            let c = match &rest[1..semi] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => ' ',
+                entity => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, semi))
+        });
+        match decoded {
+            Some((c, semi)) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn contains_ignore_case(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w.eq_ignore_ascii_case(needle))
+}
@@ -0,0 +1,2176 @@
+use base64::Engine;
+use futures::future;
+use futures::stream::{self, StreamExt};
+use indicatif::ProgressBar;
+use rustls::pki_types::ServerName;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write as _;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::{io, net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6}, time::{Duration, Instant}};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt, Interest}, net::{TcpSocket, TcpStream, UdpSocket}, time::{self, MissedTickBehavior}};
+use tokio_socks::tcp::Socks5Stream;
+use tracing::{debug, warn};
+use limiter::AdaptiveLimit;
+
+pub mod diff;
+mod dns;
+pub mod fingerprint;
+mod http;
+mod limiter;
+pub mod probes;
+pub mod schema;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod template;
+mod tls;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+pub use dns::DnsResolver;
+pub use http::HttpInfo;
+pub use limiter::{open_file_budget, ConcurrencyBounds, MinRate};
+pub use probes::{load_probes, Probe};
+pub use tls::TlsInfo;
+
+/// Result of probing a single port
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Finding {
+    /// Shared by every finding of the host, so probes do not copy it
+    pub host: Arc<str>,
+    pub port: u16,
+    pub protocol: String,
+    pub status: String,
+    pub banner: Option<String>,
+    /// Banner reduced to its stable lines by [`normalize_banner`], set
+    /// whenever there is a banner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_normalized: Option<String>,
+    /// Raw banner bytes as hex, for binary protocols the cleaned banner mangles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banner_hex: Option<String>,
+    /// Probe that produced the banner: a custom probe name, a built-in one like
+    /// "http-get", or "passive" when the service spoke first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// PTR name of the host, when reverse lookups were requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Whether the port completed a TLS handshake, only set with TLS probing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+    /// Full subject DN of the leaf certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cn: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_sans: Option<Vec<String>>,
+    /// Server name sent in the TLS handshake, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_sni: Option<String>,
+    /// HTTP status code of `GET /`, only set with title grabbing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_title: Option<String>,
+    /// Service and version recognised from the banner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Copied from --known-open results instead of probed by this scan
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub carried_forward: bool,
+    /// Address family ("ipv4" or "ipv6") that won the connect race, only set
+    /// for open ports of hosts probed over both families
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    /// Every address of the hostname that gave this same answer, when
+    /// --dedup-by-banner merged their findings into this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<Vec<String>>,
+    /// Everything read from the port with response capture, as text; control
+    /// characters other than line breaks and tabs are shown as '.'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    /// The same captured bytes, base64 encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_base64: Option<String>,
+}
+
+impl Finding {
+    /// Banner to group and compare by: the normalized one, or the cleaned
+    /// banner as is for findings saved before normalized banners were kept
+    pub fn banner_key(&self) -> Option<&str> {
+        self.banner_normalized.as_deref().or(self.banner.as_deref())
+    }
+}
+
+/// Details gathered from a port that accepted the probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpenPort {
+    pub banner: Option<String>,
+    /// Stable lines of the banner, see [`normalize_banner`]
+    pub banner_normalized: Option<String>,
+    /// Raw banner bytes in hex, with [`ScanOptions::banner_hex`]
+    pub banner_hex: Option<String>,
+    /// Name of the probe that elicited the banner, [`PASSIVE_PROBE`] when nothing was sent
+    pub probe: Option<String>,
+    /// Time taken by the connect (TCP) or the round trip (UDP)
+    pub latency: Duration,
+    /// Certificate details when TLS probing succeeded
+    pub tls: Option<TlsInfo>,
+    /// Response details when the port answered an HTTP request
+    pub http: Option<HttpInfo>,
+    /// Address the connection was made to, when it raced two of them
+    pub address: Option<IpAddr>,
+    /// Raw bytes read with [`ScanOptions::capture`], starting with the banner
+    pub response: Option<Vec<u8>>,
+}
+
+/// Outcome of a single connect or UDP probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortState {
+    // Boxed, most probes end up in one of the empty variants
+    Open(Box<OpenPort>),
+    Closed,
+    Filtered,
+    OpenFiltered,
+}
+
+impl PortState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PortState::Open(_) => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::OpenFiltered => "open|filtered",
+        }
+    }
+}
+
+/// Knobs shared by every probe in a scan
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Timeout per connect
+    pub timeout: Duration,
+    /// Connect timeouts for specific ports, taking precedence over `timeout`
+    pub port_timeouts: HashMap<u16, Duration>,
+    /// Number of simultaneous probes per host
+    pub concurrency: usize,
+    /// Adapt the number of simultaneous probes to the timeout rate, starting
+    /// from `concurrency`; `None` keeps it fixed
+    pub adaptive_concurrency: Option<ConcurrencyBounds>,
+    /// Raise the number of simultaneous probes when fewer than this many
+    /// complete per second
+    pub min_rate: Option<MinRate>,
+    /// Probe UDP instead of TCP
+    pub udp: bool,
+    /// Extra attempts for ports that time out or fail transiently
+    pub retries: RetryPolicy,
+    /// Maximum new probes launched per second, `None` for unlimited
+    pub rate: Option<u32>,
+    /// Pause before every connect attempt, per task rather than global
+    pub scan_delay: Option<Duration>,
+    /// Abandon a host when its first this many probes were all refused
+    pub skip_refused_after: Option<usize>,
+    /// Stop launching probes on a host once one of its ports is open and passes
+    /// the filter; probes already in flight still finish
+    pub first_open_only: Option<OpenFilter>,
+    /// Local address to originate probes from
+    pub source: Option<IpAddr>,
+    /// Set TCP_NODELAY on probe connections
+    pub tcp_nodelay: bool,
+    /// IP TTL (IPv6 hop limit) of outgoing probe packets
+    pub ttl: Option<u32>,
+    /// Interface index for IPv6 link-local targets, from a `%zone` suffix
+    pub scope_id: Option<u32>,
+    /// Address of the other family to race TCP connects against, RFC 8305
+    /// style: the target gets a head start of [`HAPPY_EYEBALLS_DELAY`]
+    pub race_with: Option<IpAddr>,
+    /// Proxy that TCP connects are tunneled through
+    pub proxy: Option<Proxy>,
+    /// Read a banner from open TCP ports; without it a port is recorded as
+    /// open as soon as the connect succeeds
+    pub banner: bool,
+    /// Largest banner read from an open port, in bytes
+    pub banner_bytes: usize,
+    /// How long to wait for a banner after connecting
+    pub banner_timeout: Duration,
+    /// Also keep the raw banner bytes, hex encoded
+    pub banner_hex: bool,
+    /// Keep reading open TCP ports after the banner, up to this many bytes in
+    /// total (at most [`MAX_CAPTURE_BYTES`]), and record the whole response
+    pub capture: Option<usize>,
+    /// How long response capture may take, on top of the banner read
+    pub capture_timeout: Duration,
+    /// Reconnect once to open TCP ports that sent no banner, waiting longer and sending CRLF
+    pub banner_retry: bool,
+    /// Attempt a TLS handshake on open TCP ports
+    pub tls_probe: bool,
+    /// Server name to send as SNI during the TLS probe
+    pub sni: Option<String>,
+    /// Request `/` from HTTP ports and record the status and page title
+    pub http_title: bool,
+    /// Ports treated as HTTP for title grabbing even when the banner does not say so
+    pub http_ports: Vec<u16>,
+    /// User defined probes, taking precedence over the built-in payloads
+    pub probes: Arc<[Probe]>,
+    /// Set to stop launching new probes; in-flight ones still finish
+    pub stop: Arc<AtomicBool>,
+}
+
+impl ScanOptions {
+    /// Connect timeout to use for `port`
+    pub fn timeout_for(&self, port: u16) -> Duration {
+        self.port_timeouts.get(&port).copied().unwrap_or(self.timeout)
+    }
+
+    /// The user defined probe for `port` in the current protocol, first match wins
+    pub fn probe_for(&self, port: u16) -> Option<&Probe> {
+        self.probes.iter().find(|p| p.udp == self.udp && p.ports.contains(&port))
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            timeout: Duration::from_millis(1000),
+            port_timeouts: HashMap::new(),
+            concurrency: 200,
+            adaptive_concurrency: None,
+            min_rate: None,
+            udp: false,
+            retries: RetryPolicy::default(),
+            rate: None,
+            scan_delay: None,
+            skip_refused_after: None,
+            first_open_only: None,
+            scope_id: None,
+            race_with: None,
+            source: None,
+            tcp_nodelay: false,
+            ttl: None,
+            proxy: None,
+            banner: true,
+            banner_bytes: 128,
+            banner_timeout: Duration::from_millis(200),
+            banner_hex: false,
+            capture: None,
+            capture_timeout: Duration::from_secs(2),
+            banner_retry: false,
+            tls_probe: false,
+            sni: None,
+            http_title: false,
+            http_ports: DEFAULT_HTTP_PORTS.to_vec(),
+            probes: Arc::from([]),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Which open ports count as found for [`ScanOptions::first_open_only`]:
+/// with `require_banner` only those that sent a banner, with `banner_match`
+/// only those whose banner matches
+#[derive(Clone, Debug, Default)]
+pub struct OpenFilter {
+    pub require_banner: bool,
+    pub banner_match: Option<regex::Regex>,
+}
+
+impl OpenFilter {
+    pub fn accepts(&self, banner: Option<&str>) -> bool {
+        match (banner, &self.banner_match) {
+            (None, _) => !self.require_banner && self.banner_match.is_none(),
+            (Some(banner), Some(re)) => re.is_match(banner),
+            (Some(_), None) => true,
+        }
+    }
+}
+
+/// Extra attempts a probe gets, by how it failed. A refused connect is an
+/// authoritative "closed" and is never retried.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// No answer before the timeout
+    pub timeout: u32,
+    /// Reset or aborted connects, and other errors that say nothing about the port
+    pub reset: u32,
+    /// ICMP host or network unreachable
+    pub unreachable: u32,
+}
+
+impl RetryPolicy {
+    /// `retries` for timeouts, half as many for resets and none for unreachable
+    /// hosts, which a router answered for and will answer for again
+    pub fn new(retries: u32) -> Self {
+        RetryPolicy { timeout: retries, reset: retries / 2, unreachable: 0 }
+    }
+
+    /// Override some of the counts with a spec like "timeout=3,reset=1"
+    pub fn with_overrides(mut self, spec: &str) -> anyhow::Result<Self> {
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || {
+                anyhow::anyhow!("Invalid retry policy '{}', expected KIND=N with KIND timeout, reset or unreachable", part)
+            };
+            let (kind, count) = part.split_once('=').ok_or_else(invalid)?;
+            let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+            match kind.trim() {
+                "timeout" => self.timeout = count,
+                "reset" => self.reset = count,
+                "unreachable" => self.unreachable = count,
+                "refused" => return Err(anyhow::anyhow!("Refused connects are never retried, the port is closed")),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(self)
+    }
+
+    fn attempts(&self, failure: Failure) -> u32 {
+        match failure {
+            Failure::Timeout => self.timeout,
+            Failure::Reset => self.reset,
+            Failure::Unreachable => self.unreachable,
+        }
+    }
+}
+
+// Why a probe got no definite answer, which decides how often it is retried
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Failure {
+    Timeout,
+    Reset,
+    Unreachable,
+}
+
+impl Failure {
+    fn of(e: &io::Error) -> Failure {
+        match e.kind() {
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => Failure::Unreachable,
+            io::ErrorKind::TimedOut => Failure::Timeout,
+            _ => Failure::Reset,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Failure::Timeout => "timed out",
+            Failure::Reset => "reset",
+            Failure::Unreachable => "unreachable",
+        })
+    }
+}
+
+/// Proxy used to tunnel TCP connects
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Proxy {
+    /// SOCKS5 proxy; with `remote_dns` (socks5h) hostnames are resolved by the proxy
+    Socks5 {
+        addr: SocketAddr,
+        auth: Option<(String, String)>,
+        remote_dns: bool,
+    },
+    /// HTTP proxy tunneling with `CONNECT`; hostnames are always passed on unresolved
+    HttpConnect {
+        addr: SocketAddr,
+        auth: Option<(String, String)>,
+    },
+}
+
+impl Proxy {
+    /// Parse a proxy URL like "socks5://[user:pass@]host:port", "socks5h://host:port"
+    /// or "http://[user:pass@]host:port"
+    pub async fn parse(url: &str) -> anyhow::Result<Proxy> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| anyhow::anyhow!("Invalid proxy URL: {}", url))?;
+        let (auth, host) = match rest.rsplit_once('@') {
+            Some((userinfo, host)) => {
+                let (user, pass) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_string(), pass.to_string())), host)
+            }
+            None => (None, rest),
+        };
+        let addr = tokio::net::lookup_host(host.trim_end_matches('/'))
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not resolve proxy {}: {}", host, e))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Could not resolve proxy {}", host))?;
+        match scheme.to_ascii_lowercase().as_str() {
+            "socks5" => Ok(Proxy::Socks5 { addr, auth, remote_dns: false }),
+            "socks5h" => Ok(Proxy::Socks5 { addr, auth, remote_dns: true }),
+            "http" => Ok(Proxy::HttpConnect { addr, auth }),
+            _ => Err(anyhow::anyhow!("Unsupported proxy scheme: {}", scheme)),
+        }
+    }
+
+    /// Address of the proxy server itself
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Proxy::Socks5 { addr, .. } | Proxy::HttpConnect { addr, .. } => *addr,
+        }
+    }
+
+    /// Whether target hostnames should be handed to the proxy unresolved
+    pub fn remote_dns(&self) -> bool {
+        match self {
+            Proxy::Socks5 { remote_dns, .. } => *remote_dns,
+            Proxy::HttpConnect { .. } => true,
+        }
+    }
+}
+
+// Where a probe connects to; names are only used when a proxy resolves them
+#[derive(Clone, Copy, Debug)]
+enum Dest<'a> {
+    Ip(IpAddr),
+    Name(&'a str),
+}
+
+impl fmt::Display for Dest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dest::Ip(ip) => write!(f, "{}", ip),
+            Dest::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Scan every port on every target and return one finding per probe
+pub async fn scan(targets: &[IpAddr], ports: &[u16], opts: &ScanOptions) -> Vec<Finding> {
+    scan_with_progress(targets, ports, opts, &ProgressBar::hidden()).await
+}
+
+/// Same as [`scan`] but advances `progress` once per completed probe
+pub async fn scan_with_progress(
+    targets: &[IpAddr],
+    ports: &[u16],
+    opts: &ScanOptions,
+    progress: &ProgressBar,
+) -> Vec<Finding> {
+    let mut results = Vec::new();
+    scan_each(targets, ports, opts, progress, &mut |f| results.push(f)).await;
+    results
+}
+
+/// A host to probe, either an address or a name left for the proxy to resolve
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Host {
+    Ip(IpAddr),
+    Name(String),
+}
+
+/// The ports to probe on one host
+#[derive(Clone, Debug)]
+pub struct HostPlan {
+    pub host: Host,
+    pub ports: Vec<u16>,
+    /// Connect timeout for this host, overriding [`ScanOptions::timeout`]
+    pub timeout: Option<Duration>,
+    /// SNI name for this host, overriding [`ScanOptions::sni`]
+    pub sni: Option<String>,
+    /// Zone of a link-local host, overriding [`ScanOptions::scope_id`]
+    pub scope_id: Option<u32>,
+    /// Other-family address of a dual-stack host, see [`ScanOptions::race_with`]
+    pub race_with: Option<IpAddr>,
+}
+
+/// Scan every port on every target, handing each finding to `on_finding` as soon as it completes
+pub async fn scan_each(
+    targets: &[IpAddr],
+    ports: &[u16],
+    opts: &ScanOptions,
+    progress: &ProgressBar,
+    on_finding: &mut dyn FnMut(Finding),
+) {
+    let plan: Vec<HostPlan> = targets
+        .iter()
+        .map(|&ip| HostPlan { host: Host::Ip(ip), ports: ports.to_vec(), timeout: None, sni: None, scope_id: None, race_with: None })
+        .collect();
+    scan_plan(&plan, opts, progress, on_finding, &Mutex::default()).await;
+}
+
+/// Scan all ports of a single host with bounded concurrency
+pub async fn scan_host(ip: IpAddr, ports: &[u16], opts: &ScanOptions, progress: &ProgressBar) -> Vec<Finding> {
+    let mut results = Vec::new();
+    scan_each(&[ip], ports, opts, progress, &mut |f| results.push(f)).await;
+    results
+}
+
+/// Scan a hostname without resolving it locally, the configured proxy resolves it
+pub async fn scan_remote_each(
+    name: &str,
+    ports: &[u16],
+    opts: &ScanOptions,
+    progress: &ProgressBar,
+    on_finding: &mut dyn FnMut(Finding),
+) {
+    let plan = [HostPlan { host: Host::Name(name.to_string()), ports: ports.to_vec(), timeout: None, sni: None, scope_id: None, race_with: None }];
+    scan_plan(&plan, opts, progress, on_finding, &Mutex::default()).await;
+}
+
+/// Scan every host in `plan` as one batch. Probes of all hosts share a single
+/// pool of `opts.concurrency` slots, so a slow host never holds up the rest.
+/// `report` is filled in as things happen, so it stays accurate when the
+/// scan is cut short by dropping this future.
+pub async fn scan_plan(
+    plan: &[HostPlan],
+    opts: &ScanOptions,
+    progress: &ProgressBar,
+    on_finding: &mut dyn FnMut(Finding),
+    report: &Mutex<ScanReport>,
+) {
+    // Per-host options, only the timeout, SNI, zone and raced address differ
+    let host_opts: Vec<ScanOptions> = plan
+        .iter()
+        .map(|h| ScanOptions {
+            timeout: h.timeout.unwrap_or(opts.timeout),
+            sni: h.sni.clone().or_else(|| opts.sni.clone()),
+            scope_id: h.scope_id.or(opts.scope_id),
+            race_with: h.race_with.or(opts.race_with),
+            ..opts.clone()
+        })
+        .collect();
+    let hosts: Vec<HostProgress> = plan.iter().map(|_| HostProgress::default()).collect();
+    // Formatted once per host and shared, findings only bump a reference count
+    let labels: Vec<Arc<str>> = plan
+        .iter()
+        .map(|h| match &h.host {
+            Host::Ip(ip) => Arc::from(ip.to_string()),
+            Host::Name(name) => Arc::from(name.as_str()),
+        })
+        .collect();
+    let udp = opts.udp;
+    let skip_refused_after = opts.skip_refused_after;
+    let first_open_only = opts.first_open_only.as_ref();
+    let limit = match (opts.adaptive_concurrency, opts.min_rate) {
+        (Some(bounds), _) => AdaptiveLimit::new(opts.concurrency, bounds, true),
+        (None, Some(min_rate)) => {
+            let bounds = ConcurrencyBounds { min: opts.concurrency, max: min_rate.max_concurrency.max(opts.concurrency) };
+            AdaptiveLimit::new(opts.concurrency, bounds, false)
+        }
+        // Fixed, but still lowered if the process runs out of file descriptors
+        (None, None) => {
+            let bounds = ConcurrencyBounds { min: opts.concurrency, max: opts.concurrency };
+            AdaptiveLimit::new(opts.concurrency, bounds, false)
+        }
+    };
+    let limit = &limit;
+
+    // Build tasks, each carrying its own host and port
+    let tasks = plan.iter().zip(&host_opts).zip(&hosts).zip(&labels).flat_map(|(((h, opts), host), label)| {
+        let dest = match &h.host {
+            Host::Ip(ip) => Dest::Ip(*ip),
+            Host::Name(name) => Dest::Name(name),
+        };
+        h.ports.iter().map(move |&port| (dest, port, opts, host, label))
+    });
+    // Checked lazily as tasks are pulled, so ports of an abandoned host are never launched
+    let tasks = tasks.filter(|(_, _, _, host, _)| {
+        let skipped = host.skipped.load(Ordering::Relaxed) || host.found_open.load(Ordering::Relaxed);
+        if skipped {
+            progress.inc(1);
+        }
+        !skipped
+    });
+    let tasks = tasks.map(|(dest, port, opts, host, label)| async move {
+        let (mut state, mut failure) = limited(limit, || probe_dest(dest, port, opts)).await;
+        if let Some(limit) = skip_refused_after {
+            // Give up on hosts whose first `limit` answers were all refusals
+            let answered = host.answered.fetch_add(1, Ordering::Relaxed) + 1;
+            if matches!(state, PortState::Closed) {
+                let refused = host.refused.fetch_add(1, Ordering::Relaxed) + 1;
+                if refused == limit && answered == limit {
+                    debug!("{} refused its first {} probes, skipping the rest", dest, limit);
+                    host.skipped.store(true, Ordering::Relaxed);
+                    report.lock().expect("scan report lock").skipped_hosts.push(label.to_string());
+                }
+            }
+        }
+        // Refused and open are authoritative; a failure is retried until the
+        // attempts so far reach what the policy allows for the latest one
+        let mut attempt = 0;
+        while let Some(why) = failure.filter(|&why| attempt < opts.retries.attempts(why)) {
+            attempt += 1;
+            let allowed = opts.retries.attempts(why);
+            debug!("{}:{} was {} ({}), retry {}/{}", dest, port, state.as_str(), why, attempt, allowed);
+            time::sleep(Duration::from_millis(50 * attempt as u64)).await;
+            (state, failure) = limited(limit, || probe_dest(dest, port, opts)).await;
+        }
+        if opts.banner_retry && !udp && matches!(&state, PortState::Open(open) if open.banner.is_none()) {
+            debug!("{}:{} is open but sent no banner, trying once more", dest, port);
+            let retry_opts = ScanOptions { banner_timeout: opts.banner_timeout * 2, ..opts.clone() };
+            let (again, _) = limited(limit, || probe_tcp(dest, port, &retry_opts, Some(("crlf", b"\r\n")))).await;
+            // A port that went quiet or closed meanwhile keeps its first result
+            if matches!(&again, PortState::Open(open) if open.banner.is_some()) {
+                state = again;
+            }
+        }
+        let reported = match (&state, first_open_only) {
+            (PortState::Open(open), Some(filter)) => filter.accepts(open.banner.as_deref()),
+            _ => false,
+        };
+        if reported && !host.found_open.swap(true, Ordering::Relaxed) {
+            debug!("{}:{} is open, not launching the rest of its probes", dest, port);
+        }
+        let fp = match &state {
+            PortState::Open(open) => open.banner.as_deref().and_then(|banner| {
+                let custom = opts.probe_for(port).and_then(|p| p.identify(banner));
+                custom.or_else(|| fingerprint::fingerprint(banner))
+            }),
+            _ => None,
+        };
+        Finding {
+            host: label.clone(),
+            port,
+            protocol: if udp { "udp" } else { "tcp" }.to_string(),
+            status: state.as_str().to_string(),
+            banner: match &state {
+                PortState::Open(open) => open.banner.clone(),
+                _ => None,
+            },
+            banner_normalized: match &state {
+                PortState::Open(open) => open.banner_normalized.clone(),
+                _ => None,
+            },
+            banner_hex: match &state {
+                PortState::Open(open) => open.banner_hex.clone(),
+                _ => None,
+            },
+            probe: match &state {
+                PortState::Open(open) => open.probe.clone(),
+                _ => None,
+            },
+            latency_ms: match &state {
+                PortState::Open(open) => Some(open.latency.as_millis() as u64),
+                _ => None,
+            },
+            hostname: None,
+            tls: match &state {
+                PortState::Open(_) if opts.tls_probe && !udp => Some(open_tls(&state).is_some()),
+                _ => None,
+            },
+            tls_subject: open_tls(&state).and_then(|info| info.subject.clone()),
+            tls_cn: open_tls(&state).and_then(|info| info.subject_cn.clone()),
+            tls_sans: open_tls(&state).map(|info| info.sans.clone()),
+            tls_sni: open_tls(&state).and_then(|info| info.sni.clone()),
+            http_status: open_http(&state).and_then(|info| info.status),
+            http_title: open_http(&state).and_then(|info| info.title.clone()),
+            service: fp.as_ref().map(|fp| fp.service.clone()),
+            version: fp.and_then(|fp| fp.version),
+            carried_forward: false,
+            family: match &state {
+                PortState::Open(open) => open.address.map(|ip| if ip.is_ipv4() { "ipv4" } else { "ipv6" }.to_string()),
+                _ => None,
+            },
+            addresses: None,
+            response: match &state {
+                PortState::Open(open) => open.response.as_deref().and_then(clean_response),
+                _ => None,
+            },
+            response_base64: match &state {
+                PortState::Open(open) => open.response.as_ref().filter(|raw| !raw.is_empty()).map(|raw| base64::engine::general_purpose::STANDARD.encode(raw)),
+                _ => None,
+            },
+        }
+    });
+
+    // Gate task launches on a ticker when rate limited; buffer_unordered only
+    // pulls the next task when a slot frees up, so the stricter limit wins
+    let tasks = match opts.rate.filter(|&r| r > 0) {
+        Some(rate) => {
+            let mut ticker = time::interval(Duration::from_secs_f64(1.0 / rate as f64));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let ticks = stream::unfold(ticker, |mut ticker| async move {
+                ticker.tick().await;
+                Some(((), ticker))
+            });
+            stream::iter(tasks).zip(ticks).map(|(task, _)| task).left_stream()
+        }
+        None => stream::iter(tasks).right_stream(),
+    };
+    let tasks = tasks.take_while(|_| future::ready(!opts.stop.load(Ordering::Relaxed)));
+
+    // Execute with bounded concurrency, the adaptive limit works within its maximum
+    let slots = match (opts.adaptive_concurrency, opts.min_rate) {
+        (Some(bounds), _) => bounds.max,
+        (None, Some(min_rate)) => min_rate.max_concurrency,
+        (None, None) => 0,
+    };
+    let completed = AtomicUsize::new(0);
+    let run = tasks.buffer_unordered(slots.max(opts.concurrency)).for_each(|finding| {
+        completed.fetch_add(1, Ordering::Relaxed);
+        progress.inc(1);
+        on_finding(finding);
+        async {}
+    });
+    // Once a second, compare throughput against --min-rate
+    let keep_up = async {
+        let Some(min_rate) = opts.min_rate else {
+            return future::pending().await;
+        };
+        let mut ticker = time::interval(Duration::from_secs(1));
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            limit.keep_up(completed.swap(0, Ordering::Relaxed), min_rate.pps);
+        }
+    };
+    tokio::select! {
+        _ = run => {}
+        _ = keep_up => {}
+    }
+}
+
+/// What happened to the plan as a whole, beyond the individual findings
+#[derive(Clone, Debug, Default)]
+pub struct ScanReport {
+    /// Hosts abandoned by the refused-connection heuristic
+    pub skipped_hosts: Vec<String>,
+}
+
+// Per-host tallies shared by that host's concurrent probes
+#[derive(Default)]
+struct HostProgress {
+    answered: AtomicUsize,
+    refused: AtomicUsize,
+    skipped: AtomicBool,
+    // Set with first_open_only once a port answered as open and passed its filter
+    found_open: AtomicBool,
+}
+
+// A probe could not get a socket, it says nothing about the port
+struct OutOfFiles;
+
+// "Too many open files", for the process or the whole system
+fn is_out_of_files(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+    }
+    // WSAEMFILE
+    #[cfg(windows)]
+    {
+        e.raw_os_error() == Some(10024)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = e;
+        false
+    }
+}
+
+// How often and how long a probe waits for a file descriptor before giving up
+const OUT_OF_FILES_ATTEMPTS: u32 = 100;
+const OUT_OF_FILES_PAUSE: Duration = Duration::from_millis(100);
+
+// What one probe found, and why it found nothing definite if so
+type Attempt = (PortState, Option<Failure>);
+
+// Probe under the concurrency limit. Running out of file descriptors lowers
+// the limit and runs the probe again rather than recording the port as filtered.
+async fn limited<F, Fut>(limit: &AdaptiveLimit, probe: F) -> Attempt
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<Attempt, OutOfFiles>>,
+{
+    for _ in 0..OUT_OF_FILES_ATTEMPTS {
+        let epoch = limit.acquire().await;
+        match probe().await {
+            Ok((state, failure)) => {
+                limit.release(epoch, matches!(state, PortState::Filtered));
+                return (state, failure);
+            }
+            Err(OutOfFiles) => {
+                limit.out_of_files();
+                time::sleep(OUT_OF_FILES_PAUSE).await;
+            }
+        }
+    }
+    warn!("No file descriptor became free, recording a port as filtered");
+    (PortState::Filtered, None)
+}
+
+async fn probe_dest(dest: Dest<'_>, port: u16, opts: &ScanOptions) -> Result<Attempt, OutOfFiles> {
+    if let Some(delay) = opts.scan_delay {
+        time::sleep(delay).await;
+    }
+    match dest {
+        Dest::Ip(ip) if opts.udp => probe_udp(ip, port, opts).await,
+        _ => probe_tcp(dest, port, opts, None).await,
+    }
+}
+
+/// Connect to a TCP port and grab a banner if it is open
+pub async fn probe_port(ip: IpAddr, port: u16, opts: &ScanOptions) -> PortState {
+    probe_tcp(Dest::Ip(ip), port, opts, None).await.map_or(PortState::Filtered, |(state, _)| state)
+}
+
+// `nudge` is sent, under its probe name, when the port has no probe of its own
+async fn probe_tcp(
+    dest: Dest<'_>,
+    port: u16,
+    opts: &ScanOptions,
+    nudge: Option<(&'static str, &'static [u8])>,
+) -> Result<Attempt, OutOfFiles> {
+    let started = Instant::now();
+    debug!("Connecting to {}:{}", dest, port);
+    // The timeout covers the whole connect, including any proxy handshake
+    match time::timeout(opts.timeout_for(port), connect_tcp(dest, port, opts)).await {
+        Ok(Ok(mut stream)) => {
+            let latency = started.elapsed();
+            let address = stream.peer_addr().ok().map(|addr| addr.ip()).filter(|_| opts.race_with.is_some());
+
+            // Nudge services that wait for the client to speak first
+            let (probe, payload) = match opts.probe_for(port) {
+                Some(custom) => (custom.name.as_str(), custom.payload.as_slice()),
+                None => banner_probe(port).or(nudge).unwrap_or((PASSIVE_PROBE, &[])),
+            };
+            let raw = if opts.banner {
+                if !payload.is_empty() {
+                    let _ = stream.write_all(payload).await;
+                }
+                read_banner(&mut stream, opts.banner_bytes, opts.banner_timeout).await
+            } else {
+                Vec::new()
+            };
+            let response = match opts.capture {
+                Some(limit) if opts.banner => Some(read_response(&mut stream, raw.clone(), limit, opts.capture_timeout).await),
+                _ => None,
+            };
+            let raw = raw.as_slice();
+            let banner = clean_banner(raw);
+            let banner_normalized = banner.as_ref().map(|_| normalize_banner(raw));
+            let banner_hex = hex_banner(raw, opts);
+            let probe = Some(probe.to_string()).filter(|_| !raw.is_empty());
+            drop(stream);
+            let tls = if opts.tls_probe { probe_tls(dest, port, opts).await } else { None };
+            let is_http = opts.http_ports.contains(&port) || banner.as_deref().is_some_and(|b| b.starts_with("HTTP/"));
+            let http = if opts.http_title && is_http { probe_http(dest, port, opts).await } else { None };
+            Ok((PortState::Open(Box::new(OpenPort { banner, banner_normalized, banner_hex, probe, latency, tls, http, address, response })), None))
+        }
+        Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => Ok((PortState::Closed, None)),
+        Ok(Err(e)) if is_out_of_files(&e) => Err(OutOfFiles),
+        Ok(Err(e)) => Ok((PortState::Filtered, Some(Failure::of(&e)))),
+        Err(_) => Ok((PortState::Filtered, Some(Failure::Timeout))),
+    }
+}
+
+// Read a banner, following multi-line replies like SMTP and FTP "220-" greetings
+// until their last line arrives, `limit` bytes are in or the window runs out
+async fn read_banner(stream: &mut TcpStream, limit: usize, window: Duration) -> Vec<u8> {
+    let deadline = time::Instant::now() + window;
+    let mut banner = Vec::new();
+    let mut buf = vec![0u8; limit];
+    while banner.len() < limit {
+        match time::timeout_at(deadline, stream.read(&mut buf[..limit - banner.len()])).await {
+            Ok(Ok(n)) if n > 0 => banner.extend_from_slice(&buf[..n]),
+            // EOF, a reset or the window closing ends the banner, whatever arrived before is kept
+            _ => break,
+        }
+        if !reply_continues(&banner) {
+            break;
+        }
+    }
+    banner
+}
+
+/// Upper bound for [`ScanOptions::capture`], each open port being captured
+/// can hold this much in memory
+pub const MAX_CAPTURE_BYTES: usize = 1024 * 1024;
+
+// Keep reading after the banner until `limit` bytes are in, the peer closes or
+// `window` runs out. The buffer grows with what arrives, not with `limit`.
+async fn read_response(stream: &mut TcpStream, mut response: Vec<u8>, limit: usize, window: Duration) -> Vec<u8> {
+    let limit = limit.min(MAX_CAPTURE_BYTES);
+    let deadline = time::Instant::now() + window;
+    let mut buf = vec![0u8; limit.min(16 * 1024)];
+    while response.len() < limit {
+        let want = buf.len().min(limit - response.len());
+        match time::timeout_at(deadline, stream.read(&mut buf[..want])).await {
+            Ok(Ok(n)) if n > 0 => response.extend_from_slice(&buf[..n]),
+            _ => break,
+        }
+    }
+    response.truncate(limit);
+    response
+}
+
+// A reply opened with "NNN-" goes on until a line starting "NNN " is complete
+fn reply_continues(banner: &[u8]) -> bool {
+    let Some(code) = banner.get(..3).filter(|code| code.iter().all(u8::is_ascii_digit)) else {
+        return false;
+    };
+    if banner.get(3) != Some(&b'-') {
+        return false;
+    }
+    !banner
+        .split_inclusive(|&b| b == b'\n')
+        .any(|line| line.ends_with(b"\n") && line.starts_with(code) && line.get(3) == Some(&b' '))
+}
+
+/// Estimate the round trip time to a host from a few connects. Refused
+/// connections count too since the RST still measures the path.
+pub async fn measure_rtt(ip: IpAddr, ports: &[u16], opts: &ScanOptions) -> Option<Duration> {
+    const SAMPLES: usize = 3;
+    let candidates = [80, 443, 22].iter().chain(ports.iter().take(SAMPLES));
+    let mut samples = Vec::new();
+    for &port in candidates {
+        let started = Instant::now();
+        let answered = match time::timeout(opts.timeout, connect_tcp(Dest::Ip(ip), port, opts)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => e.kind() == io::ErrorKind::ConnectionRefused,
+            Err(_) => false,
+        };
+        if answered {
+            samples.push(started.elapsed());
+            if samples.len() == SAMPLES {
+                break;
+            }
+        }
+    }
+    debug!("RTT samples for {}: {:?}", ip, samples);
+    samples.into_iter().max()
+}
+
+fn open_http(state: &PortState) -> Option<&HttpInfo> {
+    match state {
+        PortState::Open(open) => open.http.as_ref(),
+        _ => None,
+    }
+}
+
+fn open_tls(state: &PortState) -> Option<&TlsInfo> {
+    match state {
+        PortState::Open(open) => open.tls.as_ref(),
+        _ => None,
+    }
+}
+
+// How long an HTTP response may trickle in once connected
+const HTTP_READ_WINDOW: Duration = Duration::from_secs(2);
+
+/// Ports assumed to speak plain HTTP for title grabbing
+pub const DEFAULT_HTTP_PORTS: &[u16] = &[80, 8000, 8008, 8080, 8888];
+
+async fn probe_http(dest: Dest<'_>, port: u16, opts: &ScanOptions) -> Option<HttpInfo> {
+    let host = match (dest, &opts.sni) {
+        (Dest::Name(name), _) => name.to_string(),
+        (Dest::Ip(_), Some(name)) => name.clone(),
+        (Dest::Ip(IpAddr::V6(ip)), None) => format!("[{}]", ip),
+        (Dest::Ip(ip), None) => ip.to_string(),
+    };
+    let host = if port == 80 { host } else { format!("{}:{}", host, port) };
+    let stream = time::timeout(opts.timeout_for(port), connect_tcp(dest, port, opts)).await.ok()?.ok()?;
+    http::fetch(stream, &host, HTTP_READ_WINDOW).await
+}
+
+// Handshake on a fresh connection so banner probes cannot interfere
+async fn probe_tls(dest: Dest<'_>, port: u16, opts: &ScanOptions) -> Option<TlsInfo> {
+    // Without a name to offer, an IP server name makes rustls skip SNI
+    let sni = match (&opts.sni, dest) {
+        (Some(name), _) => Some(name.clone()),
+        (None, Dest::Name(name)) => Some(name.to_string()),
+        (None, Dest::Ip(_)) => None,
+    };
+    let server_name = match (&sni, dest) {
+        (Some(name), _) => ServerName::try_from(name.clone()).ok()?,
+        (None, Dest::Ip(ip)) => ServerName::IpAddress(ip.into()),
+        (None, Dest::Name(_)) => return None,
+    };
+    let attempt = async {
+        let stream = connect_tcp(dest, port, opts).await.ok()?;
+        tls::handshake(stream, server_name).await
+    };
+    let info = time::timeout(opts.timeout_for(port), attempt).await.ok().flatten()?;
+    Some(TlsInfo { sni, ..info })
+}
+
+// Open a TCP connection to the destination, directly or through the proxy
+async fn connect_tcp(dest: Dest<'_>, port: u16, opts: &ScanOptions) -> io::Result<TcpStream> {
+    match (&opts.proxy, dest) {
+        (None, Dest::Ip(ip)) => match opts.race_with {
+            Some(other) => race_connect(socket_addr(ip, port, opts), socket_addr(other, port, opts), opts).await,
+            None => open_socket(socket_addr(ip, port, opts), opts).await,
+        },
+        (None, Dest::Name(name)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} must be resolved before connecting", name),
+        )),
+        (Some(Proxy::Socks5 { addr, auth, .. }), dest) => {
+            let socket = open_socket(*addr, opts).await?;
+            let target = dest.to_string();
+            let stream = match auth {
+                Some((user, pass)) => {
+                    Socks5Stream::connect_with_password_and_socket(socket, (target.as_str(), port), user, pass).await
+                }
+                None => Socks5Stream::connect_with_socket(socket, (target.as_str(), port)).await,
+            };
+            stream.map(Socks5Stream::into_inner).map_err(|e| match e {
+                tokio_socks::Error::Io(e) => e,
+                tokio_socks::Error::ConnectionRefused => io::Error::from(io::ErrorKind::ConnectionRefused),
+                e => io::Error::other(e),
+            })
+        }
+        (Some(Proxy::HttpConnect { addr, auth }), dest) => {
+            let socket = open_socket(*addr, opts).await?;
+            let target = match dest {
+                Dest::Ip(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+                dest => format!("{}:{}", dest, port),
+            };
+            http::connect_tunnel(socket, &target, auth.as_ref()).await
+        }
+    }
+}
+
+/// Head start the preferred address gets before the other family is tried,
+/// the "Connection Attempt Delay" of RFC 8305
+pub const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+// Connect to `first`, and to `second` as well once `first` has failed or had
+// HAPPY_EYEBALLS_DELAY to itself; whichever connects first wins and the
+// other attempt is dropped
+async fn race_connect(first: SocketAddr, second: SocketAddr, opts: &ScanOptions) -> io::Result<TcpStream> {
+    let primary = open_socket(first, opts);
+    tokio::pin!(primary);
+    let first_error = tokio::select! {
+        result = &mut primary => match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => Some(e),
+        },
+        _ = time::sleep(HAPPY_EYEBALLS_DELAY) => None,
+    };
+    let fallback = open_socket(second, opts);
+    let Some(first_error) = first_error else {
+        tokio::pin!(fallback);
+        return tokio::select! {
+            result = &mut primary => match result {
+                Ok(stream) => Ok(stream),
+                Err(e) => fallback.await.map_err(|e2| pick_error(e, e2)),
+            },
+            result = &mut fallback => match result {
+                Ok(stream) => Ok(stream),
+                Err(e) => primary.await.map_err(|e2| pick_error(e, e2)),
+            },
+        };
+    };
+    fallback.await.map_err(|e| pick_error(first_error, e))
+}
+
+// A refusal from either family means the host is up and the port closed,
+// which tells more than the other family being unreachable
+fn pick_error(a: io::Error, b: io::Error) -> io::Error {
+    if b.kind() == io::ErrorKind::ConnectionRefused && a.kind() != io::ErrorKind::ConnectionRefused {
+        b
+    } else {
+        a
+    }
+}
+
+// Link-local IPv6 addresses only route with the zone they were given
+fn socket_addr(ip: IpAddr, port: u16, opts: &ScanOptions) -> SocketAddr {
+    match (ip, opts.scope_id) {
+        (IpAddr::V6(v6), Some(scope_id)) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, scope_id)),
+        _ => SocketAddr::new(ip, port),
+    }
+}
+
+// Connect a plain socket, with the configured source address and socket options
+async fn open_socket(addr: SocketAddr, opts: &ScanOptions) -> io::Result<TcpStream> {
+    if opts.source.is_none() && !opts.tcp_nodelay && opts.ttl.is_none() {
+        return TcpStream::connect(addr).await;
+    }
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    if let Some(source) = opts.source {
+        socket.bind(SocketAddr::new(source, 0))?;
+    }
+    // Best effort, not every platform supports every option
+    if opts.tcp_nodelay {
+        if let Err(e) = socket.set_nodelay(true) {
+            debug!("Could not set TCP_NODELAY: {}", e);
+        }
+    }
+    set_ttl(socket2::SockRef::from(&socket), addr.is_ipv4(), opts.ttl);
+    socket.connect(addr).await
+}
+
+fn set_ttl(socket: socket2::SockRef<'_>, ipv4: bool, ttl: Option<u32>) {
+    let Some(ttl) = ttl else {
+        return;
+    };
+    let set = if ipv4 { socket.set_ttl_v4(ttl) } else { socket.set_unicast_hops_v6(ttl) };
+    if let Err(e) = set {
+        debug!("Could not set the TTL to {}: {}", ttl, e);
+    }
+}
+
+/// Probe name recorded for banners read without sending anything
+pub const PASSIVE_PROBE: &str = "passive";
+
+// Name and bytes sent after connecting to elicit a banner, unknown ports are read passively
+fn banner_probe(port: u16) -> Option<(&'static str, &'static [u8])> {
+    match port {
+        p if DEFAULT_HTTP_PORTS.contains(&p) => Some(("http-get", b"GET / HTTP/1.0\r\n\r\n")),
+        21 | 25 | 110 | 143 | 587 => Some(("crlf", b"\r\n")),
+        _ => None,
+    }
+}
+
+/// Send a datagram to a UDP port and classify the reply
+pub async fn probe_udp_port(ip: IpAddr, port: u16, opts: &ScanOptions) -> PortState {
+    probe_udp(ip, port, opts).await.map_or(PortState::Filtered, |(state, _)| state)
+}
+
+async fn probe_udp(ip: IpAddr, port: u16, opts: &ScanOptions) -> Result<Attempt, OutOfFiles> {
+    let addr = socket_addr(ip, port, opts);
+    let local = match opts.source {
+        Some(source) => SocketAddr::new(source, 0),
+        None => if ip.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap(),
+    };
+    let socket = match UdpSocket::bind(local).await {
+        Ok(socket) => socket,
+        Err(e) if is_out_of_files(&e) => return Err(OutOfFiles),
+        Err(e) => return Ok((PortState::Filtered, Some(Failure::of(&e)))),
+    };
+    set_ttl(socket2::SockRef::from(&socket), ip.is_ipv4(), opts.ttl);
+    let (probe, payload) = opts.probe_for(port).map_or(udp_payload(port), |p| (p.name.as_str(), &p.payload));
+    let sent = async {
+        socket.connect(addr).await?;
+        socket.send(payload).await
+    };
+    if let Err(e) = sent.await {
+        return Ok((PortState::Filtered, Some(Failure::of(&e))));
+    }
+
+    // A reply means open, an ICMP port unreachable surfaces as a socket error
+    let mut buf = vec![0u8; opts.banner_bytes];
+    let started = Instant::now();
+    let recv = async {
+        loop {
+            let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+            if ready.is_error() {
+                if let Some(e) = socket.take_error()? {
+                    return Err(e);
+                }
+            }
+            match socket.try_recv(&mut buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    };
+    Ok(match time::timeout(opts.timeout_for(port), recv).await {
+        Ok(Ok(n)) => (
+            PortState::Open(Box::new(OpenPort {
+                banner: clean_banner(&buf[..n]),
+                banner_normalized: clean_banner(&buf[..n]).map(|_| normalize_banner(&buf[..n])),
+                banner_hex: hex_banner(&buf[..n], opts),
+                probe: Some(probe.to_string()).filter(|_| n > 0),
+                latency: started.elapsed(),
+                tls: None,
+                http: None,
+                address: None,
+                response: None,
+            })),
+            None,
+        ),
+        Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => (PortState::Closed, None),
+        Ok(Err(e)) => (PortState::OpenFiltered, Some(Failure::of(&e))),
+        Err(_) => (PortState::OpenFiltered, Some(Failure::Timeout)),
+    })
+}
+
+// Protocol-specific datagrams, with their probe names, for services that ignore empty packets
+fn udp_payload(port: u16) -> (&'static str, &'static [u8]) {
+    match port {
+        // DNS query for the root NS records
+        53 => ("dns", &[
+            0x13, 0x37, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+            0x00, 0x01,
+        ]),
+        // NTP v3 client request
+        123 => ("ntp", &[
+            0x1b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ]),
+        // SNMPv1 GetRequest for sysDescr.0 with community "public"
+        161 => ("snmp", &[
+            0x30, 0x29, 0x02, 0x01, 0x00, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x1c,
+            0x02, 0x04, 0x00, 0x00, 0x00, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+            0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+        ]),
+        _ => ("empty", &[]),
+    }
+}
+
+// Header lines that differ on every response and would split identical services apart
+const VOLATILE_HEADERS: &[&str] = &["date:", "expires:", "last-modified:", "set-cookie:", "etag:", "content-length:"];
+
+/// Reduce raw banner bytes to their stable lines, joined with " | ", so banners
+/// from the same service compare equal across hosts and runs
+pub fn normalize_banner(raw: &[u8]) -> String {
+    // Split before cleaning, afterwards a line break and a literal ".." look the same
+    raw.split(|&b| b == b'\n')
+        .filter_map(|line| clean_banner(line.strip_suffix(b"\r").unwrap_or(line)))
+        .filter(|line| !VOLATILE_HEADERS.iter().any(|h| starts_with_ignore_case(line, h)))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+// Compared in place, lowercasing every line for every header added up over big scans
+fn starts_with_ignore_case(line: &str, prefix: &str) -> bool {
+    line.as_bytes().get(..prefix.len()).is_some_and(|head| head.eq_ignore_ascii_case(prefix.as_bytes()))
+}
+
+// Raw banner bytes as lowercase hex, only kept when asked for
+fn hex_banner(bytes: &[u8], opts: &ScanOptions) -> Option<String> {
+    if !opts.banner_hex || bytes.is_empty() {
+        return None;
+    }
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Like clean_banner, but line structure is worth keeping in a whole response
+fn clean_response(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let cleaned: String = text
+        .chars()
+        .map(|c| if matches!(c, '\n' | '\r' | '\t') || (c.is_ascii() && !c.is_ascii_control()) { c } else { '.' })
+        .collect();
+    if cleaned.trim().is_empty() { None } else { Some(cleaned) }
+}
+
+fn clean_banner(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let cleaned = text
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '.' })
+        .collect::<String>()
+        .trim()
+        .to_string();
+    if cleaned.is_empty() { None } else { Some(cleaned) }
+}
+
+/// Parse a port spec like "22,80,443", "1-1024", "ssh,http" or "@web,22" into
+/// a sorted, unique list
+pub fn parse_ports(spec: &str) -> anyhow::Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    for part in spec.split(',') {
+        let p = part.trim();
+        if let Some(name) = p.strip_prefix('@') {
+            ports.extend_from_slice(port_category(name)?.ports);
+        } else if let Some(port) = service_port(p) {
+            ports.push(port);
+        } else if p.contains('-') {
+            let (a, b) = p.split_once('-').ok_or_else(|| anyhow::anyhow!("bad port range"))?;
+            let a: u16 = a.trim().parse()?;
+            let b: u16 = b.trim().parse()?;
+            let start = a.min(b);
+            let end = a.max(b);
+            for port in start..=end {
+                ports.push(port);
+            }
+        } else if p.chars().all(|c| c.is_ascii_digit()) {
+            ports.push(p.parse()?);
+        } else {
+            return Err(anyhow::anyhow!("Unknown service name: {}", p));
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// Parse per-port timeouts like "443=3000,mysql=5000", values in milliseconds
+pub fn parse_timeout_overrides(spec: &str) -> anyhow::Result<HashMap<u16, Duration>> {
+    let mut overrides = HashMap::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let invalid = || anyhow::anyhow!("Invalid timeout override '{}', expected PORT=MS like 443=3000", part);
+        let (port, ms) = part.split_once('=').ok_or_else(invalid)?;
+        let port = match service_port(port.trim()) {
+            Some(port) => port,
+            None => port.trim().parse::<u16>().map_err(|_| invalid())?,
+        };
+        let ms: u64 = ms.trim().parse().map_err(|_| invalid())?;
+        overrides.insert(port, Duration::from_millis(ms));
+    }
+    Ok(overrides)
+}
+
+// Well-known service names accepted in the port spec
+static SERVICES: &[(&str, u16)] = &[
+    ("echo", 7),
+    ("discard", 9),
+    ("daytime", 13),
+    ("ftp-data", 20),
+    ("ftp", 21),
+    ("ssh", 22),
+    ("telnet", 23),
+    ("smtp", 25),
+    ("time", 37),
+    ("whois", 43),
+    ("domain", 53),
+    ("dns", 53),
+    ("tftp", 69),
+    ("gopher", 70),
+    ("finger", 79),
+    ("http", 80),
+    ("kerberos", 88),
+    ("pop3", 110),
+    ("sunrpc", 111),
+    ("ident", 113),
+    ("nntp", 119),
+    ("ntp", 123),
+    ("msrpc", 135),
+    ("netbios-ns", 137),
+    ("netbios-dgm", 138),
+    ("netbios-ssn", 139),
+    ("imap", 143),
+    ("snmp", 161),
+    ("snmptrap", 162),
+    ("bgp", 179),
+    ("irc", 194),
+    ("ldap", 389),
+    ("https", 443),
+    ("microsoft-ds", 445),
+    ("smb", 445),
+    ("kpasswd", 464),
+    ("smtps", 465),
+    ("syslog", 514),
+    ("printer", 515),
+    ("submission", 587),
+    ("ipp", 631),
+    ("ldaps", 636),
+    ("rsync", 873),
+    ("ftps", 990),
+    ("imaps", 993),
+    ("pop3s", 995),
+    ("socks", 1080),
+    ("openvpn", 1194),
+    ("ms-sql-s", 1433),
+    ("mssql", 1433),
+    ("oracle", 1521),
+    ("pptp", 1723),
+    ("mqtt", 1883),
+    ("nfs", 2049),
+    ("docker", 2375),
+    ("mysql", 3306),
+    ("ms-wbt-server", 3389),
+    ("rdp", 3389),
+    ("svn", 3690),
+    ("sip", 5060),
+    ("xmpp", 5222),
+    ("postgresql", 5432),
+    ("postgres", 5432),
+    ("amqp", 5672),
+    ("vnc", 5900),
+    ("couchdb", 5984),
+    ("winrm", 5985),
+    ("redis", 6379),
+    ("irc-alt", 6667),
+    ("http-alt", 8080),
+    ("https-alt", 8443),
+    ("memcached", 11211),
+    ("mongodb", 27017),
+];
+
+/// A named group of ports, used as `@name` in a port spec
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortCategory {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub ports: &'static [u16],
+}
+
+/// Every category accepted as `@name` in a port spec
+pub static PORT_CATEGORIES: &[PortCategory] = &[
+    PortCategory {
+        name: "web",
+        description: "HTTP and HTTPS servers, proxies and admin consoles",
+        ports: &[80, 81, 443, 591, 3000, 3128, 5000, 8000, 8008, 8080, 8081, 8443, 8888, 9000, 9443],
+    },
+    PortCategory {
+        name: "db",
+        description: "SQL and NoSQL databases and caches",
+        ports: &[1433, 1521, 3306, 5432, 5984, 6379, 7000, 7001, 8086, 9042, 9200, 11211, 27017, 27018],
+    },
+    PortCategory {
+        name: "mail",
+        description: "SMTP, POP3 and IMAP, plain and TLS",
+        ports: &[25, 110, 143, 465, 587, 993, 995],
+    },
+    PortCategory {
+        name: "remote",
+        description: "Remote shells and desktops",
+        ports: &[22, 23, 512, 513, 514, 3389, 5800, 5900, 5985, 5986],
+    },
+    PortCategory {
+        name: "file",
+        description: "File transfer and sharing",
+        ports: &[20, 21, 69, 139, 445, 873, 990, 2049],
+    },
+    PortCategory {
+        name: "directory",
+        description: "DNS, LDAP and Kerberos",
+        ports: &[53, 88, 389, 464, 636, 3268, 3269],
+    },
+    PortCategory {
+        name: "mq",
+        description: "Message brokers",
+        ports: &[1883, 4222, 5671, 5672, 6650, 8883, 9092, 61613, 61616],
+    },
+    PortCategory {
+        name: "container",
+        description: "Container runtimes and orchestration APIs",
+        ports: &[2375, 2376, 2379, 2380, 6443, 10250, 10255],
+    },
+];
+
+/// Look up a port category by name, without the leading `@`
+pub fn port_category(name: &str) -> anyhow::Result<&'static PortCategory> {
+    PORT_CATEGORIES.iter().find(|c| c.name.eq_ignore_ascii_case(name)).ok_or_else(|| {
+        let names: Vec<String> = PORT_CATEGORIES.iter().map(|c| format!("@{}", c.name)).collect();
+        anyhow::anyhow!("Unknown port category @{}, expected one of {}", name, names.join(", "))
+    })
+}
+
+// Most frequently open TCP ports, in descending order (from nmap-services)
+static TOP_PORTS: &[u16] = &[
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900, 1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000,
+    8443, 8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631,
+    631, 49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156,
+    543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986,
+    13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// The `n` most commonly open ports, sorted ascending
+pub fn top_ports(n: usize) -> anyhow::Result<Vec<u16>> {
+    if n == 0 || n > TOP_PORTS.len() {
+        return Err(anyhow::anyhow!("--top-ports must be between 1 and {}", TOP_PORTS.len()));
+    }
+    let mut ports = TOP_PORTS[..n].to_vec();
+    ports.sort_unstable();
+    Ok(ports)
+}
+
+/// Look up the port of a well-known service name
+pub fn service_port(name: &str) -> Option<u16> {
+    SERVICES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, port)| port)
+}
+
+/// Canonical service name for a port, the first entry in the table wins
+pub fn service_name(port: u16) -> Option<&'static str> {
+    SERVICES.iter().find(|&&(_, p)| p == port).map(|&(name, _)| name)
+}
+
+/// Targets read from a file
+#[derive(Clone, Debug, Default)]
+pub struct TargetList {
+    pub targets: Vec<String>,
+    /// Addresses generated from `10.0.0.1-50` style ranges and `*` wildcards
+    pub expanded: usize,
+}
+
+/// Read everything piped to stdin, for inputs given as `-`. Refuses to wait on
+/// an interactive terminal; `what` names the input in errors.
+pub fn read_stdin(what: &str) -> anyhow::Result<String> {
+    use std::io::{IsTerminal, Read};
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Err(anyhow::anyhow!("Expected {} on stdin, but nothing is piped in", what));
+    }
+    let mut content = String::new();
+    stdin
+        .read_to_string(&mut content)
+        .map_err(|e| anyhow::anyhow!("Could not read {} from stdin: {}", what, e))?;
+    Ok(content)
+}
+
+/// Read targets from a file, one per line, skipping blanks and '#' comments.
+/// A path of `-` reads them from stdin. IPv4 octet ranges and wildcards are
+/// expanded into individual addresses.
+pub fn load_targets(path: &Path) -> anyhow::Result<TargetList> {
+    let (content, path) = if path == Path::new("-") {
+        (read_stdin("targets")?, Path::new("stdin"))
+    } else {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Could not read target file {}: {}", path.display(), e))?;
+        (content, path)
+    };
+    let mut list = TargetList::default();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match expand_octets(line).map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), n + 1, e))? {
+            Some(ips) => {
+                list.expanded += ips.len();
+                list.targets.extend(ips.iter().map(Ipv4Addr::to_string));
+            }
+            None => list.targets.push(line.to_string()),
+        }
+    }
+    if list.targets.is_empty() {
+        return Err(anyhow::anyhow!("No targets found in {}", path.display()));
+    }
+    Ok(list)
+}
+
+/// Expand "192.168.1.*", "10.0.0.1-50" or "10.0.0.5-10.0.3.200" into addresses.
+/// A `*` octet stands for 0-255 and a short range covers the last octet; the
+/// long form is any inclusive range, see [`expand_ip_range`]. Returns `None`
+/// for anything that is not such a pattern, like plain IPs and hostnames.
+pub fn expand_octets(pattern: &str) -> anyhow::Result<Option<Vec<Ipv4Addr>>> {
+    if let Some((start, end)) = parse_ip_range(pattern) {
+        if !start.is_ipv4() {
+            return Ok(None);
+        }
+        let ips = expand_ip_range(start, end)?;
+        return Ok(Some(
+            ips.into_iter()
+                .filter_map(|ip| match ip {
+                    IpAddr::V4(ip) => Some(ip),
+                    IpAddr::V6(_) => None,
+                })
+                .collect(),
+        ));
+    }
+    let parts: Vec<&str> = pattern.split('.').collect();
+    let is_octet = |p: &str| p == "*" || (!p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()));
+    let is_pattern = parts.len() == 4
+        && parts[..3].iter().all(|p| is_octet(p))
+        && parts[3].split('-').all(is_octet)
+        && (pattern.contains('*') || pattern.contains('-'));
+    if !is_pattern {
+        return Ok(None);
+    }
+
+    let mut octets: Vec<std::ops::RangeInclusive<u8>> = Vec::with_capacity(4);
+    for part in &parts {
+        let range = match part.split_once('-') {
+            _ if *part == "*" => 0..=255,
+            Some((low, high)) => {
+                let (low, high) = (parse_octet(low, pattern)?, parse_octet(high, pattern)?);
+                if low > high {
+                    return Err(anyhow::anyhow!("Range {} runs backwards", pattern));
+                }
+                low..=high
+            }
+            None => {
+                let octet = parse_octet(part, pattern)?;
+                octet..=octet
+            }
+        };
+        octets.push(range);
+    }
+    let size: u64 = octets.iter().map(|r| (*r.end() - *r.start()) as u64 + 1).product();
+    if size > MAX_CIDR_HOSTS {
+        return Err(anyhow::anyhow!(
+            "{} expands to {} addresses, the maximum is {}",
+            pattern,
+            size,
+            MAX_CIDR_HOSTS
+        ));
+    }
+
+    let mut ips = Vec::with_capacity(size as usize);
+    for a in octets[0].clone() {
+        for b in octets[1].clone() {
+            for c in octets[2].clone() {
+                ips.extend(octets[3].clone().map(|d| Ipv4Addr::new(a, b, c, d)));
+            }
+        }
+    }
+    Ok(Some(ips))
+}
+
+/// Split "10.0.0.5-10.0.3.200" or "[fd00::1]-[fd00::ff]" into its endpoints.
+/// Returns `None` unless both sides are IP addresses.
+pub fn parse_ip_range(s: &str) -> Option<(IpAddr, IpAddr)> {
+    let (start, end) = s.split_once('-')?;
+    let parse = |ip: &str| ip.trim().trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+    Some((parse(start)?, parse(end)?))
+}
+
+/// Enumerate every address from `start` to `end` inclusive, in numeric order.
+/// Both ends must be the same family, and the range is held to the same size
+/// limit as CIDR blocks.
+pub fn expand_ip_range(start: IpAddr, end: IpAddr) -> anyhow::Result<Vec<IpAddr>> {
+    let (first, last) = match (start, end) {
+        (IpAddr::V4(s), IpAddr::V4(e)) => (u32::from(s) as u128, u32::from(e) as u128),
+        (IpAddr::V6(s), IpAddr::V6(e)) => (u128::from(s), u128::from(e)),
+        _ => return Err(anyhow::anyhow!("Range {}-{} mixes IPv4 and IPv6", start, end)),
+    };
+    if first > last {
+        return Err(anyhow::anyhow!("Range {}-{} runs backwards", start, end));
+    }
+    let size = last - first + 1;
+    if size > MAX_CIDR_HOSTS as u128 {
+        return Err(anyhow::anyhow!(
+            "Range {}-{} expands to {} addresses, the maximum is {}",
+            start,
+            end,
+            size,
+            MAX_CIDR_HOSTS
+        ));
+    }
+    Ok((first..=last)
+        .map(|n| match start {
+            IpAddr::V4(_) => IpAddr::V4((n as u32).into()),
+            IpAddr::V6(_) => IpAddr::V6(n.into()),
+        })
+        .collect())
+}
+
+fn parse_octet(s: &str, pattern: &str) -> anyhow::Result<u8> {
+    s.parse().map_err(|_| anyhow::anyhow!("Invalid octet {} in {}", s, pattern))
+}
+
+/// Read ports from a file, each line a port spec as accepted by [`parse_ports`].
+/// Blank lines and '#' comments are skipped. Returns a sorted, unique list.
+pub fn load_ports(path: &Path) -> anyhow::Result<Vec<u16>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Could not read ports file {}: {}", path.display(), e))?;
+    let mut ports = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        let spec = line.split('#').next().unwrap_or_default().trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let parsed = parse_ports(spec).map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), n + 1, e))?;
+        ports.extend(parsed);
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+// Largest CIDR expansion allowed, /16 worth of addresses
+const MAX_CIDR_HOSTS: u64 = 1 << 16;
+
+/// Resolve a hostname or IP, or enumerate the hosts of an IPv4 CIDR block or
+/// a start-end address range
+pub async fn expand_target(target: &str, resolve: &ResolveOptions) -> anyhow::Result<Vec<IpAddr>> {
+    if let Some((start, end)) = parse_ip_range(target) {
+        if !resolve.family.matches(&start) {
+            return Err(anyhow::anyhow!("Range {} does not match the requested address family", target));
+        }
+        return expand_ip_range(start, end);
+    }
+    match target.split_once('/') {
+        Some(_) if resolve.family == IpFamily::V6 => {
+            Err(anyhow::anyhow!("CIDR ranges are IPv4 only, cannot use them with --ipv6"))
+        }
+        Some((addr, prefix)) => expand_cidr(addr, prefix),
+        None if resolve.all_ips => resolve_host_all(target, resolve).await,
+        None => Ok(vec![resolve_host(target, resolve).await?]),
+    }
+}
+
+fn expand_cidr(addr: &str, prefix: &str) -> anyhow::Result<Vec<IpAddr>> {
+    let ip: IpAddr = addr
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid CIDR address: {}", addr))?;
+    let IpAddr::V4(ip) = ip else {
+        return Err(anyhow::anyhow!("IPv6 CIDR ranges are not supported yet"));
+    };
+    let prefix: u32 = prefix
+        .trim()
+        .parse()
+        .ok()
+        .filter(|p| *p <= 32)
+        .ok_or_else(|| anyhow::anyhow!("Invalid CIDR prefix: /{}", prefix))?;
+
+    let size = 1u64 << (32 - prefix);
+    if size > MAX_CIDR_HOSTS {
+        return Err(anyhow::anyhow!(
+            "CIDR range /{} is too large, the maximum is /16 ({} addresses)",
+            prefix,
+            MAX_CIDR_HOSTS
+        ));
+    }
+
+    let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+    let network = u32::from(ip) & mask;
+    let broadcast = network | !mask;
+    // Skip network and broadcast addresses except for /31 and /32
+    let (first, last) = if prefix >= 31 { (network, broadcast) } else { (network + 1, broadcast - 1) };
+    Ok((first..=last).map(|n| IpAddr::V4(n.into())).collect())
+}
+
+/// An address or CIDR block, IPv4 or IPv6, used to match hosts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Parse "10.0.0.1", "10.0.0.0/24" or "fe80::/64"
+    pub fn parse(s: &str) -> anyhow::Result<IpNet> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim_matches(['[', ']'])
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid address or CIDR: {}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| anyhow::anyhow!("Invalid CIDR prefix in {}", s))?,
+            None => max,
+        };
+        Ok(IpNet { addr, prefix })
+    }
+
+    /// The fewest CIDR blocks covering `start` to `end` inclusive, so ranges of
+    /// any size can be matched without listing their addresses
+    pub fn cover(start: IpAddr, end: IpAddr) -> anyhow::Result<Vec<IpNet>> {
+        let (mut first, last, bits) = match (start, end) {
+            (IpAddr::V4(s), IpAddr::V4(e)) => (u32::from(s) as u128, u32::from(e) as u128, 32),
+            (IpAddr::V6(s), IpAddr::V6(e)) => (u128::from(s), u128::from(e), 128),
+            _ => return Err(anyhow::anyhow!("Range {}-{} mixes IPv4 and IPv6", start, end)),
+        };
+        if first > last {
+            return Err(anyhow::anyhow!("Range {}-{} runs backwards", start, end));
+        }
+        let to_addr = |n: u128| if bits == 32 { IpAddr::V4((n as u32).into()) } else { IpAddr::V6(n.into()) };
+        let block_end = |start: u128, size: u32| start | u128::MAX.checked_shl(size).map_or(u128::MAX, |m| !m);
+        let mut nets = Vec::new();
+        loop {
+            // The largest block aligned at `first` that stays within the range
+            let mut size = first.trailing_zeros().min(bits);
+            while block_end(first, size) > last {
+                size -= 1;
+            }
+            nets.push(IpNet { addr: to_addr(first), prefix: (bits - size) as u8 });
+            match block_end(first, size).checked_add(1) {
+                Some(next) if next <= last => first = next,
+                _ => return Ok(nets),
+            }
+        }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse a comma separated list of addresses and CIDR blocks
+pub fn parse_ip_nets(spec: &str) -> anyhow::Result<Vec<IpNet>> {
+    spec.split(',').map(str::trim).filter(|s| !s.is_empty()).map(IpNet::parse).collect()
+}
+
+/// Read addresses, CIDR blocks and start-end ranges from a file in masscan's
+/// `--excludefile` format: one or more per line, separated by commas or spaces.
+/// Blank lines and '#' comments are skipped.
+pub fn load_ip_nets(path: &Path) -> anyhow::Result<Vec<IpNet>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Could not read exclude file {}: {}", path.display(), e))?;
+    let mut nets = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        let spec = line.split('#').next().unwrap_or_default();
+        for entry in spec.split([',', ' ', '\t']).filter(|s| !s.is_empty()) {
+            let parsed = match parse_ip_range(entry) {
+                Some((start, end)) => IpNet::cover(start, end),
+                None => IpNet::parse(entry).map(|net| vec![net]),
+            };
+            nets.extend(parsed.map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), n + 1, e))?);
+        }
+    }
+    Ok(nets)
+}
+
+/// Drop the hosts inside `excluded`, returning how many were dropped. `races`
+/// pairs the IPv6 address of a dual-stack name with its IPv4 address; when
+/// one side of a pair is excluded the pair is split, and an IPv4 address
+/// whose partner went is scanned on its own.
+pub fn exclude_hosts(hosts: &mut Vec<IpAddr>, races: &mut HashMap<IpAddr, IpAddr>, excluded: &[IpNet]) -> usize {
+    let is_excluded = |ip: &IpAddr| excluded.iter().any(|net| net.contains(ip));
+    let before = hosts.len();
+    hosts.retain(|ip| !is_excluded(ip));
+    let dropped = before - hosts.len();
+    let mut orphans = Vec::new();
+    races.retain(|primary, other| match (is_excluded(primary), is_excluded(other)) {
+        (false, false) => true,
+        (true, false) => {
+            orphans.push(*other);
+            false
+        }
+        (_, true) => false,
+    });
+    // Sorted, the map iterates in no particular order
+    orphans.sort_unstable();
+    for ip in orphans {
+        if !hosts.contains(&ip) {
+            hosts.push(ip);
+        }
+    }
+    dropped
+}
+
+/// Address family restriction applied when resolving targets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum IpFamily {
+    /// Either family, IPv4 is preferred when a name has both
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            IpFamily::Any => true,
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+/// How hostnames are turned into addresses
+#[derive(Clone, Debug, Default)]
+pub struct ResolveOptions {
+    pub family: IpFamily,
+    /// Keep every resolved address instead of just the preferred one
+    pub all_ips: bool,
+    /// Query this server instead of the system resolver
+    pub dns: Option<DnsResolver>,
+    /// Give up on a name after this long
+    pub timeout: Option<Duration>,
+}
+
+/// Split the `%zone` suffix off an IPv6 link-local target like "fe80::1%eth0" or
+/// "[fe80::1%2]", returning the bare address and the interface index. Targets
+/// without a zone are returned unchanged.
+pub fn split_zone(target: &str) -> anyhow::Result<(&str, Option<u32>)> {
+    let host = target.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(target);
+    let Some((addr, zone)) = host.split_once('%') else {
+        return Ok((target, None));
+    };
+    match addr.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) if ip.is_unicast_link_local() => {}
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Zone IDs only apply to IPv6 link-local (fe80::/10) addresses, not {}",
+                addr
+            ))
+        }
+    }
+    let scope_id = match zone.parse::<u32>() {
+        Ok(index) => index,
+        Err(_) => interface_index(zone)?,
+    };
+    Ok((addr, Some(scope_id)))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> anyhow::Result<u32> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| anyhow::anyhow!("Invalid interface name: {}", name))?;
+    // SAFETY: c_name is a valid NUL terminated string for the duration of the call
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(anyhow::anyhow!("Unknown network interface: {}", name)),
+        index => Ok(index),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(name: &str) -> anyhow::Result<u32> {
+    Err(anyhow::anyhow!("Interface names are not supported here, use the numeric zone index instead of {}", name))
+}
+
+/// First address of the requested family on a network interface, for binding
+/// probes to it. IPv6 link-local addresses are skipped, they need a zone to be usable.
+#[cfg(unix)]
+pub fn interface_address(name: &str, family: IpFamily) -> anyhow::Result<IpAddr> {
+    // Also rejects names that are no interface at all, with a clearer error than "no address"
+    interface_index(name)?;
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills `list` with a linked list that stays valid until freeifaddrs,
+    // each ifa_addr points at a sockaddr of the size its sa_family says
+    unsafe {
+        if libc::getifaddrs(&mut list) != 0 {
+            return Err(anyhow::anyhow!("Could not list network interfaces: {}", io::Error::last_os_error()));
+        }
+        let mut entry = list;
+        while let Some(ifa) = entry.as_ref() {
+            entry = ifa.ifa_next;
+            if ifa.ifa_addr.is_null() || std::ffi::CStr::from_ptr(ifa.ifa_name).to_bytes() != name.as_bytes() {
+                continue;
+            }
+            match (*ifa.ifa_addr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    addrs.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))));
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    addrs.push(IpAddr::V6(sin6.sin6_addr.s6_addr.into()));
+                }
+                _ => {}
+            }
+        }
+        libc::freeifaddrs(list);
+    }
+    addrs.retain(|ip| family.matches(ip) && !matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local()));
+    let preferred = addrs.iter().find(|ip| ip.is_ipv4()).or(addrs.first());
+    preferred.copied().ok_or_else(|| match family {
+        IpFamily::Any => anyhow::anyhow!("Interface {} has no usable address", name),
+        IpFamily::V4 => anyhow::anyhow!("Interface {} has no IPv4 address", name),
+        IpFamily::V6 => anyhow::anyhow!("Interface {} has no usable IPv6 address", name),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn interface_address(name: &str, _family: IpFamily) -> anyhow::Result<IpAddr> {
+    Err(anyhow::anyhow!("--interface is not supported here, use --source-address with the address of {}", name))
+}
+
+/// Resolve a hostname or IP literal to a single address of the requested family.
+/// Bracketed IPv6 literals like "[::1]" are accepted.
+pub async fn resolve_host(host: &str, resolve: &ResolveOptions) -> anyhow::Result<IpAddr> {
+    let addrs = resolve_host_all(host, resolve).await?;
+    let preferred = addrs.iter().find(|ip| ip.is_ipv4()).unwrap_or(&addrs[0]);
+    Ok(*preferred)
+}
+
+/// Resolve a hostname for happy eyeballs: an IPv6 address to try first, and an
+/// IPv4 one to race against it when the name has both
+pub async fn resolve_dual_stack(host: &str, resolve: &ResolveOptions) -> anyhow::Result<(IpAddr, Option<IpAddr>)> {
+    let addrs = resolve_host_all(host, resolve).await?;
+    let v6 = addrs.iter().find(|ip| ip.is_ipv6()).copied();
+    let v4 = addrs.iter().find(|ip| ip.is_ipv4()).copied();
+    match (v6, v4) {
+        (Some(v6), v4) => Ok((v6, v4)),
+        (None, _) => Ok((addrs[0], None)),
+    }
+}
+
+/// Resolve a hostname or IP literal to every distinct address of the requested family
+pub async fn resolve_host_all(host: &str, resolve: &ResolveOptions) -> anyhow::Result<Vec<IpAddr>> {
+    let family = resolve.family;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+
+    // Try to parse as IP first
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        if !family.matches(&ip) {
+            return Err(anyhow::anyhow!("{} does not match the requested address family", ip));
+        }
+        return Ok(vec![ip]);
+    }
+    
+    // DNS lookup
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for ip in lookup_cached(host, resolve).await? {
+        if !family.matches(&ip) {
+            debug!("Ignoring {} for {}, wrong address family", ip, host);
+        } else if !addrs.contains(&ip) {
+            addrs.push(ip);
+        }
+    }
+    debug!("Resolved {} to {:?}", host, addrs);
+    if !addrs.is_empty() {
+        return Ok(addrs);
+    }
+    
+    Err(anyhow::anyhow!("Failed to resolve host: {}", host))
+}
+
+// How long a resolved name is reused within a run
+const DNS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+// When a name was resolved and what it resolved to
+type CachedLookup = (Instant, Vec<IpAddr>);
+
+// A name only has one answer per DNS server (None for the system resolver)
+// and address family asked for
+type LookupKey = (String, Option<IpAddr>, IpFamily);
+
+// Forward lookups shared by every target of the run, so repeated names hit the resolver once
+async fn lookup_cached(host: &str, resolve: &ResolveOptions) -> anyhow::Result<Vec<IpAddr>> {
+    static CACHE: OnceLock<Mutex<HashMap<LookupKey, CachedLookup>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
+    let key = match &resolve.dns {
+        Some(dns) => {
+            let (server, family) = dns.source();
+            (host.to_ascii_lowercase(), Some(server), family)
+        }
+        None => (host.to_ascii_lowercase(), None, resolve.family),
+    };
+
+    if let Some((resolved_at, ips)) = cache.lock().expect("dns cache lock").get(&key) {
+        if resolved_at.elapsed() < DNS_CACHE_TTL {
+            debug!("DNS cache hit for {}", host);
+            return Ok(ips.clone());
+        }
+    }
+    let lookup = async {
+        match &resolve.dns {
+            Some(dns) => dns.lookup(host).await,
+            None => Ok(tokio::net::lookup_host(format!("{}:0", host)).await?.map(|a| a.ip()).collect()),
+        }
+    };
+    let ips: Vec<IpAddr> = match resolve.timeout {
+        Some(timeout) => time::timeout(timeout, lookup)
+            .await
+            .map_err(|_| anyhow::anyhow!("Resolving {} timed out after {}ms", host, timeout.as_millis()))??,
+        None => lookup.await?,
+    };
+    cache.lock().expect("dns cache lock").insert(key, (Instant::now(), ips.clone()));
+    Ok(ips)
+}
+
+/// Look up the PTR name of an address, `None` if there is none or it takes too long
+pub async fn reverse_lookup(ip: IpAddr, timeout: Duration) -> Option<String> {
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+    match time::timeout(timeout, lookup).await {
+        // getnameinfo falls back to the numeric form when there is no PTR record
+        Ok(Ok(Ok(name))) if name != ip.to_string() => Some(name),
+        _ => None,
+    }
+}
+
+/// Reverse lookups for many addresses at once, skipping those without a name
+pub async fn reverse_lookup_all(ips: &[IpAddr], timeout: Duration) -> HashMap<IpAddr, String> {
+    stream::iter(ips.iter().copied())
+        .map(|ip| async move { reverse_lookup(ip, timeout).await.map(|name| (ip, name)) })
+        .buffer_unordered(32)
+        .filter_map(|x| async move { x })
+        .collect()
+        .await
+}
+
+/// Findings gathered so far by an interrupted scan. On disk it is JSONL, one
+/// finding per line, so a running scan only ever appends to it.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Checkpoint {
+    pub findings: Vec<Finding>,
+}
+
+impl Checkpoint {
+    /// Read a checkpoint, also accepting the single JSON document older
+    /// versions wrote. A last line cut short by a crash is dropped.
+    pub fn load(path: &Path) -> anyhow::Result<Checkpoint> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Could not read checkpoint {}: {}", path.display(), e))?;
+        if let Ok(checkpoint) = serde_json::from_str::<Checkpoint>(&content) {
+            return Ok(checkpoint);
+        }
+        let lines: Vec<(usize, &str)> = content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()).collect();
+        let mut findings = Vec::with_capacity(lines.len());
+        for (i, &(n, line)) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(finding) => findings.push(finding),
+                Err(e) if i + 1 == lines.len() && e.is_eof() => {
+                    warn!("Ignoring the unfinished last line of checkpoint {}", path.display());
+                }
+                Err(e) => return Err(anyhow::anyhow!("Invalid checkpoint {}:{}: {}", path.display(), n + 1, e)),
+            }
+        }
+        Ok(Checkpoint { findings })
+    }
+
+    /// Write to a temporary file first so an interrupted save never leaves a truncated checkpoint
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // Next to the checkpoint under its full name plus a suffix, so no
+        // extension the user picked can make it the checkpoint itself
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".partial");
+        let tmp = PathBuf::from(tmp);
+        let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+        for finding in &self.findings {
+            serde_json::to_writer(&mut out, finding)?;
+            out.write_all(b"\n")?;
+        }
+        out.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Ports already probed, by host, gathered in one pass
+    pub fn completed(&self) -> HashMap<&str, HashSet<u16>> {
+        let mut done: HashMap<&str, HashSet<u16>> = HashMap::new();
+        for f in &self.findings {
+            done.entry(&f.host).or_default().insert(f.port);
+        }
+        done
+    }
+}
+
+// Flush and fsync the checkpoint after this many appended findings, or once
+// this much time went by, whichever comes first
+const CHECKPOINT_SYNC_EVERY: usize = 1000;
+const CHECKPOINT_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Appends findings to a checkpoint file as they complete, so the cost of
+/// checkpointing grows with the findings and not with how often it is saved
+pub struct CheckpointWriter {
+    file: io::BufWriter<fs::File>,
+    unsynced: usize,
+    synced_at: Instant,
+}
+
+impl CheckpointWriter {
+    /// Start the file over with what `resumed` already holds, then append to it
+    pub fn start(path: &Path, resumed: &Checkpoint) -> io::Result<CheckpointWriter> {
+        resumed.save(path)?;
+        let file = fs::OpenOptions::new().append(true).open(path)?;
+        Ok(CheckpointWriter { file: io::BufWriter::new(file), unsynced: 0, synced_at: Instant::now() })
+    }
+
+    pub fn append(&mut self, finding: &Finding) -> io::Result<()> {
+        serde_json::to_writer(&mut self.file, finding)?;
+        self.file.write_all(b"\n")?;
+        self.unsynced += 1;
+        if self.unsynced >= CHECKPOINT_SYNC_EVERY || self.synced_at.elapsed() >= CHECKPOINT_SYNC_INTERVAL {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Make everything appended so far durable
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.unsynced = 0;
+        self.synced_at = Instant::now();
+        Ok(())
+    }
+}
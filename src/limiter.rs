@@ -0,0 +1,170 @@
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use tracing::{debug, trace, warn};
+
+// Share of probes in a window that may time out before backing off
+const BACKOFF_THRESHOLD: f64 = 0.2;
+
+// Descriptors kept free for stdio, output files, DNS lookups and the runtime itself
+const RESERVED_FDS: usize = 32;
+
+/// How many probes may hold a socket at once under the open file limit, at
+/// most `wanted`. The soft limit is raised towards the hard one first when it
+/// is too low. Platforms without such a limit get `wanted` back.
+pub fn open_file_budget(wanted: usize) -> usize {
+    #[cfg(unix)]
+    {
+        match rlimit::increase_nofile_limit((wanted + RESERVED_FDS) as u64) {
+            Ok(soft) => wanted.min((soft as usize).saturating_sub(RESERVED_FDS).max(1)),
+            Err(e) => {
+                debug!("Could not read the open file limit: {}", e);
+                wanted
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        wanted
+    }
+}
+
+/// Bounds for the adaptive concurrency controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConcurrencyBounds {
+    pub min: usize,
+    pub max: usize,
+}
+
+/// A floor on completed probes per second, reached by raising the number of
+/// simultaneous probes up to `max_concurrency`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MinRate {
+    pub pps: u32,
+    pub max_concurrency: usize,
+}
+
+/// AIMD limit on in-flight probes: halved when too many probes in a window
+/// time out, raised by one after every clean window. A window is as many
+/// probes as the limit allows; probes launched before a backoff do not count
+/// towards the next one, so a single overload only halves the limit once.
+/// Without `backoff` the limit only moves through [`AdaptiveLimit::keep_up`].
+pub(crate) struct AdaptiveLimit {
+    bounds: ConcurrencyBounds,
+    backoff: bool,
+    state: Mutex<State>,
+    released: Notify,
+}
+
+struct State {
+    limit: usize,
+    // Lowered for good when the process runs out of file descriptors
+    max: usize,
+    in_flight: usize,
+    // Bumped on every backoff
+    epoch: u64,
+    done: usize,
+    timed_out: usize,
+}
+
+impl AdaptiveLimit {
+    pub(crate) fn new(start: usize, bounds: ConcurrencyBounds, backoff: bool) -> Self {
+        AdaptiveLimit {
+            bounds,
+            backoff,
+            state: Mutex::new(State {
+                limit: start.clamp(bounds.min, bounds.max),
+                max: bounds.max,
+                in_flight: 0,
+                epoch: 0,
+                done: 0,
+                timed_out: 0,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Wait for a free slot under the current limit, returning the epoch to release it with
+    pub(crate) async fn acquire(&self) -> u64 {
+        loop {
+            // Registered before checking so a release in between is not missed
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit {
+                    state.in_flight += 1;
+                    return state.epoch;
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Give the slot back, recording whether the probe timed out
+    pub(crate) fn release(&self, epoch: u64, timed_out: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        if self.backoff && epoch == state.epoch {
+            state.record(timed_out, self.bounds.min);
+        }
+        drop(state);
+        self.released.notify_waiters();
+    }
+
+    /// Give the slot back after the probe could not get a socket, capping the
+    /// limit at the probes still holding one
+    pub(crate) fn out_of_files(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        let limit = state.in_flight.max(1);
+        if limit < state.limit {
+            warn!(
+                "Ran out of file descriptors with {} probes in flight, lowering concurrency to {} (raise the limit with `ulimit -n`)",
+                state.in_flight + 1,
+                limit
+            );
+            state.limit = limit;
+            state.max = limit;
+            state.epoch += 1;
+        }
+        drop(state);
+        self.released.notify_waiters();
+    }
+
+    /// Scale the limit up in proportion to how far `observed` probes per
+    /// second fell short of `target`
+    pub(crate) fn keep_up(&self, observed: usize, target: u32) {
+        let mut state = self.state.lock().unwrap();
+        if observed >= target as usize || state.limit >= state.max {
+            return;
+        }
+        // At most doubled per check, a slow start says little about the network yet
+        let wanted = state.limit * target as usize / observed.max(1);
+        let limit = wanted.clamp(state.limit + 1, state.limit * 2).min(state.max);
+        debug!("{} probes/sec is below --min-rate {}, concurrency {} -> {}", observed, target, state.limit, limit);
+        state.limit = limit;
+        drop(state);
+        self.released.notify_waiters();
+    }
+}
+
+impl State {
+    fn record(&mut self, timed_out: bool, min: usize) {
+        self.done += 1;
+        self.timed_out += timed_out as usize;
+        if self.done < self.limit {
+            return;
+        }
+        let rate = self.timed_out as f64 / self.done as f64;
+        if rate > BACKOFF_THRESHOLD && self.limit > min {
+            let limit = (self.limit / 2).max(min);
+            debug!("{:.0}% of probes timed out, concurrency {} -> {}", rate * 100.0, self.limit, limit);
+            self.limit = limit;
+            self.epoch += 1;
+        } else if rate <= BACKOFF_THRESHOLD && self.limit < self.max {
+            self.limit += 1;
+            trace!("Probes look healthy, concurrency raised to {}", self.limit);
+        }
+        self.done = 0;
+        self.timed_out = 0;
+    }
+}
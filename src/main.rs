@@ -1,45 +1,630 @@
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use clap_complete::Shell;
+use clapscan::diff::{self, HostDiff};
+use clapscan::template::Template;
+use clapscan::{
+    exclude_hosts, expand_target, interface_address, load_ports, load_probes, load_ip_nets, load_targets, measure_rtt, parse_ip_nets, parse_ip_range, parse_ports,
+    parse_timeout_overrides, read_stdin, resolve_dual_stack, reverse_lookup_all, scan_plan, schema, service_name, split_zone, top_ports, Checkpoint, CheckpointWriter,
+    open_file_budget, ConcurrencyBounds, Finding, MinRate, TargetList,
+    DnsResolver, Host, HostPlan, IpFamily, Proxy, OpenFilter, ResolveOptions, RetryPolicy, ScanOptions, MAX_CAPTURE_BYTES, PORT_CATEGORIES, ScanReport, DEFAULT_HTTP_PORTS,
+};
 use futures::stream::{self, StreamExt};
-use serde::Serialize;
-use std::{net::SocketAddr, time::Duration};
-use tokio::{io::AsyncReadExt, net::TcpStream, time};
+use std::{collections::{BTreeMap, HashMap, HashSet}, io, net::IpAddr, time::{Duration, Instant}};
 
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::{signal, time};
 use directories::UserDirs;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use owo_colors::OwoColorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use tracing::{info, warn, Level};
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Serialize, Debug)]
 #[command(name = "clapscan", about = "Simple port scanner")]
 struct Args {
-    /// Target hostname or IP
-    target: String,
+    /// Target hostname, IP or IPv4 CIDR range (IPv6 CIDR is not supported yet);
+    /// link-local IPv6 addresses take a zone, e.g. fe80::1%eth0
+    #[arg(required_unless_present_any = [
+        "target_file", "generate_completions", "print_schema", "diff", "benchmark", "list_categories",
+    ])]
+    target: Option<String>,
+
+    /// File with one target per line ('#' starts a comment), "-" for stdin
+    #[arg(long = "target-file", conflicts_with = "target")]
+    target_file: Option<PathBuf>,
+
+    /// Only use IPv4 addresses
+    #[arg(short = '4', long = "ipv4", conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only use IPv6 addresses (by default IPv4 is preferred when a name has both)
+    #[arg(short = '6', long = "ipv6")]
+    ipv6: bool,
+
+    /// Scan every address a hostname resolves to, not just the first
+    #[arg(long = "all-ips", default_value_t = false)]
+    all_ips: bool,
+
+    /// With --all-ips, merge findings with the same port, state and banner on several
+    /// addresses of one hostname into a single entry that lists every address
+    #[arg(long = "dedup-by-banner", requires = "all_ips", conflicts_with = "output_dir")]
+    dedup_by_banner: bool,
+
+    /// Also write the findings as they were before --dedup-by-banner merged them,
+    /// one JSON object per line
+    #[arg(long = "dedup-raw", value_name = "FILE", requires = "dedup_by_banner")]
+    dedup_raw: Option<PathBuf>,
+
+    /// Race TCP connects to the IPv6 and IPv4 address of dual-stack hostnames,
+    /// giving IPv6 a 250 ms head start (RFC 8305); open ports record the family that won
+    #[arg(long = "happy-eyeballs", conflicts_with_all = ["ipv4", "ipv6", "all_ips", "udp", "proxy"])]
+    happy_eyeballs: bool,
 
-    /// Ports "22,80,443" or "1-1024"
-    #[arg(short = 'p', long = "ports", default_value = "1-1000")]
+    /// Resolve hostnames with this DNS server instead of the system resolver
+    /// (-4 and -6 ask it for only A or only AAAA records)
+    #[arg(long = "dns-server", env = "CLAPSCAN_DNS_SERVER")]
+    dns_server: Option<IpAddr>,
+
+    /// Give up resolving a hostname after this many milliseconds
+    #[arg(long = "dns-timeout-ms", default_value_t = 5000)]
+    dns_timeout_ms: u64,
+
+    /// Ports "22,80,443", "1-1024", service names like "ssh,http" or categories like "@web,@db"
+    /// (see --list-categories); "-" reads them from stdin
+    #[arg(short = 'p', long = "ports", env = "CLAPSCAN_PORTS", default_value = "1-1000")]
     ports: String,
 
+    /// Scan the N most common ports instead of a port spec
+    #[arg(long = "top-ports", env = "CLAPSCAN_TOP_PORTS")]
+    top_ports: Option<usize>,
+
+    /// Read more ports from a file, one spec per line; merged with -p or --top-ports when given
+    #[arg(long = "ports-file")]
+    ports_file: Option<PathBuf>,
+
+    /// Ports to skip, same syntax as --ports
+    #[arg(long = "exclude-ports", env = "CLAPSCAN_EXCLUDE_PORTS")]
+    exclude_ports: Option<String>,
+
     /// Concurrency, number of simultaneous connect tasks
-    #[arg(short = 'c', long = "concurrency", default_value = "200")]
+    #[arg(short = 'c', long = "concurrency", env = "CLAPSCAN_CONCURRENCY", default_value = "200")]
     concurrency: usize,
 
+    /// Lower the concurrency when many probes time out and raise it again as they recover,
+    /// starting from --concurrency
+    #[arg(long = "adaptive-concurrency", default_value_t = false)]
+    adaptive_concurrency: bool,
+
+    /// Floor for --adaptive-concurrency
+    #[arg(long = "min-concurrency", default_value = "10", requires = "adaptive_concurrency", value_parser = clap::value_parser!(u32).range(1..))]
+    min_concurrency: u32,
+
+    /// Ceiling for --adaptive-concurrency (default: --concurrency) or --min-rate (default: 4x --concurrency)
+    #[arg(long = "max-concurrency", value_parser = clap::value_parser!(u32).range(1..))]
+    max_concurrency: Option<u32>,
+
+    /// Keep at least this many probes per second completing, adding simultaneous probes
+    /// up to --max-concurrency when the scan falls behind
+    #[arg(long = "min-rate", env = "CLAPSCAN_MIN_RATE", conflicts_with = "adaptive_concurrency", value_parser = clap::value_parser!(u32).range(1..))]
+    min_rate: Option<u32>,
+
     /// Timeout per connect in milliseconds
-    #[arg(long = "timeout-ms", default_value = "1000")]
+    #[arg(long = "timeout-ms", env = "CLAPSCAN_TIMEOUT_MS", default_value = "1000")]
     timeout_ms: u64,
 
-    /// Output JSON
+    /// Per-port connect timeouts in milliseconds, e.g. 443=3000,3306=5000
+    #[arg(long = "timeout-override")]
+    timeout_override: Option<String>,
+
+    /// Never probe these hosts, comma separated IPs and CIDR ranges
+    #[arg(long = "exclude-hosts", env = "CLAPSCAN_EXCLUDE_HOSTS")]
+    exclude_hosts: Option<String>,
+
+    /// Never probe the hosts listed in this file, in masscan/nmap --excludefile format:
+    /// IPs, CIDR ranges and start-end ranges, '#' starts a comment; combines with --exclude-hosts
+    #[arg(long = "exclude-file", visible_alias = "excludefile", value_name = "PATH")]
+    exclude_file: Option<PathBuf>,
+
+    /// Scan UDP ports instead of TCP
+    #[arg(long = "udp", default_value_t = false)]
+    udp: bool,
+
+    /// Timing template from 0 (paranoid) to 5 (insane), presetting concurrency, timeout,
+    /// rate and scan delay; those flags still override it
+    #[arg(short = 'T', long = "timing", value_name = "0-5", value_parser = clap::value_parser!(u8).range(..=5))]
+    timing: Option<u8>,
+
+    /// Maximum new connections per second, 0 for unlimited
+    #[arg(long = "rate", env = "CLAPSCAN_RATE", default_value = "0")]
+    rate: u32,
+
+    /// Abandon a host when its first N probes are all refused (default N: 5)
+    #[arg(long = "skip-refused-host", value_name = "N", num_args = 0..=1, default_missing_value = "5", value_parser = clap::value_parser!(u32).range(1..))]
+    skip_refused_host: Option<u32>,
+
+    /// Stop probing a host at its first open port and report only that one, for
+    /// quick "is anything listening" sweeps
+    #[arg(long = "first-open-only", default_value_t = false)]
+    first_open_only: bool,
+
+    /// Sleep this many milliseconds before each connect attempt; with -c 1 probes run strictly one at a time
+    #[arg(long = "scan-delay-ms", default_value = "0")]
+    scan_delay_ms: u64,
+
+    /// Local IP address to send probes from
+    #[arg(long = "source-address", env = "CLAPSCAN_SOURCE_ADDRESS")]
+    source_address: Option<IpAddr>,
+
+    /// Set TCP_NODELAY on probe connections
+    #[arg(long = "tcp-nodelay", default_value_t = false)]
+    tcp_nodelay: bool,
+
+    /// IP TTL (IPv6 hop limit) for probe packets; a low value only reaches nearby hops
+    #[arg(long = "ttl", value_name = "N", value_parser = clap::value_parser!(u32).range(1..=255))]
+    ttl: Option<u32>,
+
+    /// Send probes from the address of this network interface, e.g. eth0 or wg0
+    #[arg(long = "interface", value_name = "NAME", conflicts_with = "source_address")]
+    interface: Option<String>,
+
+    /// Tunnel TCP connects through a proxy, e.g. socks5://127.0.0.1:9050
+    /// (socks5h:// lets the proxy resolve hostnames) or an HTTP CONNECT proxy, http://host:3128
+    #[arg(long = "proxy", env = "CLAPSCAN_PROXY", conflicts_with = "udp")]
+    #[serde(serialize_with = "serialize_redacted_proxy")]
+    proxy: Option<String>,
+
+    /// Look up PTR names for scanned hosts
+    #[arg(long = "resolve-ptr", default_value_t = false)]
+    resolve_ptr: bool,
+
+    /// Order ports are probed in (default: ascending, for --top-ports and port files too)
+    #[arg(long = "scan-order", value_enum)]
+    scan_order: Option<ScanOrder>,
+
+    /// Shuffle the port order to avoid sequential scan patterns, same as --scan-order random
+    #[arg(long = "randomize", default_value_t = false, conflicts_with = "scan_order")]
+    randomize: bool,
+
+    /// Seed for the random scan order and --sample to get a reproducible run
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    /// Only scan a random PERCENT of the selected ports, for a quick first look
+    #[arg(long = "sample", value_name = "PERCENT", value_parser = parse_percent)]
+    sample: Option<f64>,
+
+    /// Record open ports as soon as the connect succeeds, without waiting for a banner
+    #[arg(
+        long = "no-banner",
+        default_value_t = false,
+        conflicts_with_all = ["udp", "banner_hex", "banner_retry", "probes", "only_with_banner", "banner_match", "capture_response"]
+    )]
+    no_banner: bool,
+
+    /// Maximum banner size to read in bytes (up to 65536)
+    #[arg(long = "banner-bytes", default_value = "128", value_parser = clap::value_parser!(u32).range(1..=65536))]
+    banner_bytes: u32,
+
+    /// How long to wait for a banner in milliseconds (up to 60000)
+    #[arg(long = "banner-timeout-ms", default_value = "200", value_parser = clap::value_parser!(u64).range(..=60000))]
+    banner_timeout_ms: u64,
+
+    /// Also record raw banner bytes as hex (banner_hex), for binary protocols
+    #[arg(long = "banner-hex", default_value_t = false)]
+    banner_hex: bool,
+
+    /// Keep reading open ports after the banner and record up to BYTES of the
+    /// response, as text and base64 (up to 1048576)
+    #[arg(
+        long = "capture-response",
+        value_name = "BYTES",
+        conflicts_with = "udp",
+        value_parser = clap::value_parser!(u32).range(1..=MAX_CAPTURE_BYTES as i64)
+    )]
+    capture_response: Option<u32>,
+
+    /// How long to keep reading for --capture-response in milliseconds (up to 60000)
+    #[arg(
+        long = "capture-timeout-ms",
+        default_value = "2000",
+        requires = "capture_response",
+        value_parser = clap::value_parser!(u64).range(1..=60000)
+    )]
+    capture_timeout_ms: u64,
+
+    /// Reconnect once to open ports that sent no banner, waiting twice as long and sending a CRLF
+    #[arg(long = "banner-retry", default_value_t = false, conflicts_with = "udp")]
+    banner_retry: bool,
+
+    /// Try a TLS handshake on open ports and record the certificate names
+    #[arg(long = "tls-probe", default_value_t = false)]
+    tls_probe: bool,
+
+    /// TOML file of custom probes, see probes.example.toml
+    #[arg(long = "probes", env = "CLAPSCAN_PROBES")]
+    probes: Option<PathBuf>,
+
+    /// Fetch / from HTTP ports and record the status code and page title
+    #[arg(long = "http-title", default_value_t = false)]
+    http_title: bool,
+
+    /// Ports to fetch titles from in addition to those whose banner looks like HTTP (default: 80,8000,8008,8080,8888)
+    #[arg(long = "http-ports", requires = "http_title")]
+    http_ports: Option<String>,
+
+    /// Server name to send as SNI in the TLS probe (default: the target hostname, if one was given)
+    #[arg(long = "sni", requires = "tls_probe", value_parser = parse_sni)]
+    sni: Option<String>,
+
+    /// Derive the connect timeout from the measured round trip time to each host,
+    /// falling back to --timeout-ms when it cannot be measured
+    #[arg(long = "adaptive-timeout", default_value_t = false, conflicts_with = "udp")]
+    adaptive_timeout: bool,
+
+    /// Retries for ports that time out; connection resets get half as many, refusals none
+    #[arg(long = "retries", env = "CLAPSCAN_RETRIES", default_value = "0")]
+    retries: u32,
+
+    /// Retries per kind of failure, overriding what --retries implies, e.g. timeout=3,reset=1,unreachable=0
+    #[arg(long = "retry-policy", value_name = "SPEC")]
+    retry_policy: Option<String>,
+
+    /// Also report ports that refused the connection
+    #[arg(long = "show-closed", default_value_t = false)]
+    show_closed: bool,
+
+    /// Also report ports that did not answer before the timeout
+    #[arg(long = "show-filtered", default_value_t = false)]
+    show_filtered: bool,
+
+    /// Record progress in this file as probes complete, so the scan can be resumed
+    #[arg(long = "checkpoint")]
+    checkpoint: Option<PathBuf>,
+
+    /// Resume from a checkpoint file, skipping ports it already covers
+    #[arg(long = "resume")]
+    resume: Option<PathBuf>,
+
+    /// Saved JSON or JSONL results whose open ports are carried forward instead of probed again
+    #[arg(long = "known-open", value_name = "FILE")]
+    known_open: Option<PathBuf>,
+
+    /// Only report ports that returned a banner; the summary still counts the rest
+    #[arg(long = "only-with-banner", default_value_t = false)]
+    only_with_banner: bool,
+
+    /// Only report ports whose banner matches REGEX, e.g. 'OpenSSH_7\.'
+    #[arg(long = "banner-match", value_name = "REGEX")]
+    banner_match: Option<String>,
+
+    /// Warn about hosts that look like honeypots or tarpits: almost every port open, or many sharing one banner
+    #[arg(long = "detect-honeypot", default_value_t = false)]
+    detect_honeypot: bool,
+
+    /// Share of scanned ports that must be open to flag a host (needs at least 20 ports scanned)
+    #[arg(long = "honeypot-open-percent", value_name = "PERCENT", default_value_t = 80.0, value_parser = parse_percent, requires = "detect_honeypot")]
+    honeypot_open_percent: f64,
+
+    /// Number of open ports with the same banner that flags a host
+    #[arg(long = "honeypot-same-banner", value_name = "N", default_value_t = 10, requires = "detect_honeypot")]
+    honeypot_same_banner: usize,
+
+    /// Print each open port to stderr as soon as it is found, whatever the output format
+    #[arg(long = "live", default_value_t = false)]
+    live: bool,
+
+    /// Print only the number of open ports ({"open": N} with JSON output)
+    #[arg(long = "count-only", default_value_t = false)]
+    count_only: bool,
+
+    /// Exit with status 1 when no open ports are found
+    #[arg(long = "fail-on-empty", default_value_t = false)]
+    fail_on_empty: bool,
+
+    /// After text output, group open ports by banner to spot identical services
+    #[arg(long = "summary", default_value_t = false)]
+    summary: bool,
+
+    /// Stop the scan after this many seconds and report what was found so far
+    #[arg(long = "max-runtime", env = "CLAPSCAN_MAX_RUNTIME")]
+    max_runtime: Option<u64>,
+
+    /// Resolve targets and ports, print what would be scanned and exit without probing
+    #[arg(long = "dry-run", default_value_t = false)]
+    dry_run: bool,
+
+    /// More diagnostic output (-v for debug, -vv for trace)
+    #[arg(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Only print results and warnings
+    #[arg(short = 'q', long = "quiet", conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Disable colored text output (also honors NO_COLOR)
+    #[arg(long = "no-color", default_value_t = false)]
+    no_color: bool,
+
+    /// Output format
+    #[arg(long = "output-format", env = "CLAPSCAN_OUTPUT_FORMAT", value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
+    /// Order findings are listed in, whatever order probes finished in (JSONL is written as they finish)
+    #[arg(long = "sort", value_enum, default_value_t = SortKey::Host)]
+    sort: SortKey,
+
+    /// Write results to a file instead of stdout
+    #[arg(short = 'o', long = "output", env = "CLAPSCAN_OUTPUT")]
+    output: Option<PathBuf>,
+
+    /// Print each finding as this template instead of the text table, e.g. "{host}:{port} {banner}";
+    /// placeholders: {host} {port} {protocol} {status} {banner} {latency} {service} {version}
+    #[arg(long = "format-template", value_name = "TEMPLATE")]
+    format_template: Option<String>,
+
+    /// Write one file per scanned host into DIR, named after the host, instead of a combined output
+    #[arg(long = "output-dir", value_name = "DIR", conflicts_with_all = ["output", "count_only"])]
+    output_dir: Option<PathBuf>,
+
+    /// Config file with default options (default: ./clapscan.toml, then ~/.config/clapscan/clapscan.toml)
+    #[arg(long = "config", env = "CLAPSCAN_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Compare two saved JSON or JSONL scans and report ports that opened, closed or changed banner
+    #[arg(long = "diff", num_args = 2, value_names = ["OLD", "NEW"], conflicts_with_all = ["target", "target_file"])]
+    diff: Option<Vec<PathBuf>>,
+
+    /// After scanning, serve the results at http://ADDR/results.json until Ctrl-C
+    #[cfg(feature = "serve")]
+    #[arg(long = "serve", value_name = "ADDR")]
+    serve: Option<std::net::SocketAddr>,
+
+    /// When the scan is done, POST the JSON report to this URL
+    #[cfg(feature = "webhook")]
+    #[arg(long = "webhook", value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Bearer token sent with the --webhook request
+    #[cfg(feature = "webhook")]
+    #[arg(long = "webhook-token", env = "CLAPSCAN_WEBHOOK_TOKEN", requires = "webhook")]
+    #[serde(serialize_with = "serialize_redacted_secret")]
+    webhook_token: Option<String>,
+
+    /// Give up on the --webhook request after this many milliseconds
+    #[cfg(feature = "webhook")]
+    #[arg(long = "webhook-timeout-ms", default_value_t = 10000, requires = "webhook")]
+    webhook_timeout_ms: u64,
+
+    /// Scan the target (default 127.0.0.1) repeatedly at rising concurrency levels, print the
+    /// throughput and timeout rate of each and recommend a --concurrency
+    #[arg(long = "benchmark", default_value_t = false, conflicts_with_all = ["diff", "dry_run", "output", "output_dir"])]
+    benchmark: bool,
+
+    /// Print the port categories usable as @name in --ports and exit
+    #[arg(long = "list-categories", default_value_t = false)]
+    list_categories: bool,
+
+    /// Print the effective options, after config file, environment and defaults, as JSON and exit
+    #[arg(long = "print-config", default_value_t = false)]
+    print_config: bool,
+
+    /// Include the effective options in JSON output, as with --print-config
+    #[arg(long = "embed-config", default_value_t = false)]
+    embed_config: bool,
+
+    /// Print the JSON schema of --output-format json and exit
+    #[arg(long = "print-schema", default_value_t = false)]
+    print_schema: bool,
+
+    /// Check JSON and JSONL output against the schema before writing it, for catching format regressions
+    #[arg(long = "validate-output", default_value_t = false)]
+    validate_output: bool,
+
+    /// Print a shell completion script and exit
+    #[arg(long = "generate-completions", value_enum, hide = true)]
+    #[serde(skip)]
+    generate_completions: Option<Shell>,
+
+    /// Output JSON (same as --output-format json)
     #[arg(long = "json", default_value_t = false)]
     json: bool,
+
+    /// Write JSON on a single line instead of pretty-printed, for storage and streaming
+    #[arg(long = "compact", default_value_t = false)]
+    compact: bool,
 }
 
-#[derive(Serialize)]
-struct Finding {
-    host: String,
-    port: u16,
-    status: &'static str,
-    banner: Option<String>,
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    Grepable,
+    /// One JSON object per line, written as each probe completes
+    Jsonl,
+    /// Minimal nmap-compatible XML
+    Xml,
+    /// Prometheus exposition text for the node_exporter textfile collector
+    Prometheus,
+    /// Open ports only, "22,80,443", prefixed with "host: " when scanning several hosts
+    Ports,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "txt",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Grepable => "gnmap",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Xml => "xml",
+            OutputFormat::Prometheus => "prom",
+            OutputFormat::Ports => "txt",
+        }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ScanOrder {
+    Asc,
+    Desc,
+    Random,
 }
 
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    /// By host, then port
+    Host,
+    /// By port, then host
+    Port,
+    /// Fastest first, findings without a latency last
+    Latency,
+}
+
+/// Defaults read from clapscan.toml. Precedence, highest first:
+/// command line flags, CLAPSCAN_* environment variables, the config file, built-in defaults.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    concurrency: Option<usize>,
+    timeout_ms: Option<u64>,
+    ports: Option<String>,
+    output_format: Option<OutputFormat>,
+}
+
+fn load_config(explicit: Option<&Path>) -> anyhow::Result<Config> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let home_config = UserDirs::new().map(|dirs| dirs.home_dir().join(".config/clapscan/clapscan.toml"));
+            match [Some(PathBuf::from("clapscan.toml")), home_config].into_iter().flatten().find(|p| p.exists()) {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            }
+        }
+    };
+    let content = fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Could not read config {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))
+}
+
+// Config values only replace options the user did not set explicitly
+fn apply_config(args: &mut Args, matches: &ArgMatches, config: Config) {
+    let is_default = |id: &str| matches!(matches.value_source(id), None | Some(ValueSource::DefaultValue));
+    if let Some(concurrency) = config.concurrency.filter(|_| is_default("concurrency")) {
+        args.concurrency = concurrency;
+    }
+    if let Some(timeout_ms) = config.timeout_ms.filter(|_| is_default("timeout_ms")) {
+        args.timeout_ms = timeout_ms;
+    }
+    if let Some(ports) = config.ports.filter(|_| is_default("ports")) {
+        args.ports = ports;
+    }
+    if let Some(format) = config.output_format.filter(|_| is_default("output_format")) {
+        args.output_format = format;
+    }
+}
+
+/// Values set by one --timing template
+struct Timing {
+    name: &'static str,
+    concurrency: usize,
+    timeout_ms: u64,
+    rate: u32,
+    scan_delay_ms: u64,
+}
+
+// Indexed by the template number; T3 matches the built-in defaults
+const TIMINGS: [Timing; 6] = [
+    Timing { name: "paranoid", concurrency: 1, timeout_ms: 5000, rate: 0, scan_delay_ms: 5000 },
+    Timing { name: "sneaky", concurrency: 1, timeout_ms: 3000, rate: 0, scan_delay_ms: 1000 },
+    Timing { name: "polite", concurrency: 10, timeout_ms: 2000, rate: 100, scan_delay_ms: 100 },
+    Timing { name: "normal", concurrency: 200, timeout_ms: 1000, rate: 0, scan_delay_ms: 0 },
+    Timing { name: "aggressive", concurrency: 1000, timeout_ms: 500, rate: 0, scan_delay_ms: 0 },
+    Timing { name: "insane", concurrency: 5000, timeout_ms: 250, rate: 0, scan_delay_ms: 0 },
+];
+
+// The template wins over the config file but not over flags or environment variables
+fn apply_timing(args: &mut Args, matches: &ArgMatches) {
+    let Some(timing) = args.timing.map(|t| &TIMINGS[t as usize]) else {
+        return;
+    };
+    let is_default = |id: &str| matches!(matches.value_source(id), None | Some(ValueSource::DefaultValue));
+    if is_default("concurrency") {
+        args.concurrency = timing.concurrency;
+    }
+    if is_default("timeout_ms") {
+        args.timeout_ms = timing.timeout_ms;
+    }
+    if is_default("rate") {
+        args.rate = timing.rate;
+    }
+    if is_default("scan_delay_ms") {
+        args.scan_delay_ms = timing.scan_delay_ms;
+    }
+}
+
+// One spec per line or comma separated, '#' starts a comment like in --ports-file
+fn ports_from_stdin() -> anyhow::Result<String> {
+    let content = read_stdin("a port spec")?;
+    let specs: Vec<&str> = content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim().trim_matches(','))
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() {
+        return Err(anyhow::anyhow!("No ports given on stdin"));
+    }
+    Ok(specs.join(","))
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.trim_end_matches('%').parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(format!("'{}' is not a percentage between 0 and 100", value)),
+    }
+}
+
+fn parse_sni(name: &str) -> Result<String, String> {
+    rustls::pki_types::ServerName::try_from(name)
+        .map(|_| name.to_string())
+        .map_err(|_| format!("'{}' is not a valid server name", name))
+}
+
+// Adaptive timeouts are this multiple of the measured RTT, within these bounds
+const RTT_MULTIPLIER: u32 = 4;
+const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_millis(50);
+const MAX_ADAPTIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn adaptive_timeout(ip: IpAddr, ports: &[u16], opts: &ScanOptions) -> Duration {
+    match measure_rtt(ip, ports, opts).await {
+        Some(rtt) => {
+            let timeout = (rtt * RTT_MULTIPLIER).clamp(MIN_ADAPTIVE_TIMEOUT, MAX_ADAPTIVE_TIMEOUT);
+            info!("Measured RTT to {} of {:?}, using a {:?} timeout", ip, rtt, timeout);
+            timeout
+        }
+        None => {
+            info!("Could not measure RTT to {}, using a {:?} timeout", ip, opts.timeout);
+            opts.timeout
+        }
+    }
+}
+
+// How long to wait for a PTR answer before leaving the hostname out
+const PTR_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Handle install/uninstall flags before parsing args
@@ -50,77 +635,1714 @@ async fn main() -> anyhow::Result<()> {
         return uninstall_from_path().await;
     }
 
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
 
-    let ports = parse_ports(&args.ports)?;
+    if args.print_schema {
+        println!("{}", serde_json::to_string_pretty(&schemars::schema_for!(JsonReport))?);
+        return Ok(());
+    }
+    if args.list_categories {
+        for category in PORT_CATEGORIES {
+            let ports: Vec<String> = category.ports.iter().map(u16::to_string).collect();
+            println!("@{:<10} {}\n            {}", category.name, category.description, ports.join(","));
+        }
+        return Ok(());
+    }
+    if let Some(shell) = args.generate_completions {
+        clap_complete::generate(shell, &mut Args::command(), "clapscan", &mut io::stdout());
+        return Ok(());
+    }
+
+    // --top-ports and -p conflict, unless one of them only comes from the environment
+    let ports_source = matches.value_source("ports");
+    match (matches.value_source("top_ports"), ports_source) {
+        (Some(top), Some(ports)) if top == ports => {
+            return Err(anyhow::anyhow!("--top-ports cannot be used with --ports"));
+        }
+        (Some(ValueSource::EnvVariable), Some(ValueSource::CommandLine)) => args.top_ports = None,
+        _ => {}
+    }
+
+    let config = load_config(args.config.as_deref())?;
+    apply_config(&mut args, &matches, config);
+    apply_timing(&mut args, &matches);
+
+    if args.ports == "-" {
+        if args.target_file.as_deref() == Some(Path::new("-")) {
+            return Err(anyhow::anyhow!("Only one of --ports and --target-file can be read from stdin"));
+        }
+        args.ports = ports_from_stdin()?;
+    }
+
+    if let Some(paths) = &args.diff {
+        let changes = diff::diff(&diff::load_findings(&paths[0])?, &diff::load_findings(&paths[1])?);
+        let report = if args.json || args.output_format == OutputFormat::Json {
+            format!("{}\n", to_json(&changes, args.compact)?)
+        } else {
+            render_diff(&changes)
+        };
+        match &args.output {
+            Some(path) => fs::write(path, report)
+                .map_err(|e| anyhow::anyhow!("Could not write output file {}: {}", path.display(), e))?,
+            None => print!("{}", report),
+        }
+        return Ok(());
+    }
+
+    // Diagnostics go to stderr so stdout only ever carries results
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .init();
+
+    if let Some(t) = args.timing {
+        info!(
+            "Timing T{} ({}): concurrency {}, timeout {}ms, rate {}, scan delay {}ms",
+            t,
+            TIMINGS[t as usize].name,
+            args.concurrency,
+            args.timeout_ms,
+            if args.rate > 0 { format!("{}/sec", args.rate) } else { "unlimited".to_string() },
+            args.scan_delay_ms
+        );
+    }
+
+    // A ports file replaces the default range but adds to an explicit -p
+    let ports_given = matches!(ports_source, Some(ValueSource::CommandLine | ValueSource::EnvVariable));
+    let mut ports = match (args.top_ports, &args.ports_file) {
+        (Some(n), _) => top_ports(n)?,
+        (None, Some(_)) if !ports_given => Vec::new(),
+        (None, _) => parse_ports(&args.ports)?,
+    };
+    if let Some(path) = &args.ports_file {
+        let mut seen: HashSet<u16> = ports.iter().copied().collect();
+        ports.extend(load_ports(path)?.into_iter().filter(|p| seen.insert(*p)));
+        ports.sort_unstable();
+    }
+    if let Some(spec) = &args.exclude_ports {
+        let excluded: HashSet<u16> = parse_ports(spec)?.into_iter().collect();
+        ports.retain(|p| !excluded.contains(p));
+        if ports.is_empty() {
+            info!("All ports were excluded, nothing to scan");
+            return Ok(());
+        }
+    }
+    let banner_match = match &args.banner_match {
+        Some(pattern) => Some(
+            regex::Regex::new(pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid --banner-match pattern '{}': {}", pattern, e))?,
+        ),
+        None => None,
+    };
+    // What --first-open-only counts as a host's open port: one that will be reported
+    let open_filter = OpenFilter { require_banner: args.only_with_banner, banner_match: banner_match.clone() };
+    let template = args.format_template.as_deref().map(Template::parse).transpose()?;
+    let order = if args.randomize { Some(ScanOrder::Random) } else { args.scan_order };
+    if args.seed.is_some() && order != Some(ScanOrder::Random) && args.sample.is_none() {
+        return Err(anyhow::anyhow!("--seed only applies to --scan-order random and --sample"));
+    }
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    };
+    let port_sample = args.sample.map(|percent| {
+        let total = ports.len();
+        let amount = ((total as f64 * percent / 100.0).ceil() as usize).clamp(1, total);
+        // Picked by index and put back in order, so the ports stay ascending
+        let mut keep = rand::seq::index::sample(&mut rng, total, amount).into_vec();
+        keep.sort_unstable();
+        ports = keep.into_iter().map(|i| ports[i]).collect();
+        info!("Sampled {} of {} ports ({}%)", amount, total, percent);
+        PortSample { percent, sampled: amount, total }
+    });
+    match order {
+        None => {}
+        Some(ScanOrder::Asc) => ports.sort_unstable(),
+        Some(ScanOrder::Desc) => ports.sort_unstable_by(|a, b| b.cmp(a)),
+        Some(ScanOrder::Random) => ports.shuffle(&mut rng),
+    }
     let timeout = Duration::from_millis(args.timeout_ms);
+    let port_timeouts = match &args.timeout_override {
+        Some(spec) => parse_timeout_overrides(spec)?,
+        None => HashMap::new(),
+    };
 
-    println!("Starting scan of {} ({} ports)...", args.target, ports.len());
-    let ip = resolve_host(&args.target).await?;
-    println!("Target IP: {}", ip);
-
-    // Build tasks
-    let tasks = ports.into_iter().map(|port| {
-        let ip = ip;
-        let timeout = timeout;
-        async move {
-            let addr = SocketAddr::new(ip, port);
-            match time::timeout(timeout, TcpStream::connect(addr)).await {
-                Ok(Ok(mut stream)) => {
-                    // Try to read banner
-                    let mut buf = [0u8; 128];
-                    let banner = match time::timeout(Duration::from_millis(200), stream.read(&mut buf)).await {
-                        Ok(Ok(n)) if n > 0 => {
-                            let text = String::from_utf8_lossy(&buf[..n]);
-                            let cleaned = text
-                                .chars()
-                                .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '.' })
-                                .collect::<String>()
-                                .trim()
-                                .to_string();
-                            if cleaned.is_empty() { None } else { Some(cleaned) }
-                        }
-                        _ => None,
-                    };
-                    Some(Finding {
-                        host: ip.to_string(),
-                        port,
-                        status: "open",
-                        banner,
-                    })
+    let TargetList { targets, expanded: hosts_expanded } = match &args.target_file {
+        Some(path) => load_targets(path)?,
+        // Only --benchmark runs without a target, it measures this machine by default
+        None => TargetList { targets: vec![args.target.clone().unwrap_or_else(|| "127.0.0.1".to_string())], expanded: 0 },
+    };
+    if hosts_expanded > 0 {
+        info!("Target ranges expanded to {} hosts", hosts_expanded);
+    }
+
+    let format = if args.json { OutputFormat::Json } else { args.output_format };
+    if template.is_some() && format != OutputFormat::Text {
+        return Err(anyhow::anyhow!("--format-template only applies to text output"));
+    }
+    if args.compact && !matches!(format, OutputFormat::Json | OutputFormat::Jsonl) {
+        return Err(anyhow::anyhow!("--compact only applies to JSON output"));
+    }
+    if args.dedup_by_banner && format == OutputFormat::Jsonl {
+        return Err(anyhow::anyhow!("--dedup-by-banner needs every finding at once, it cannot stream JSONL"));
+    }
+
+    let config = EffectiveConfig {
+        options: &args,
+        output_format: format,
+        port_count: ports.len(),
+        target_count: targets.len(),
+        probe_count: targets.len() * ports.len(),
+    };
+    if args.print_config {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
+    let config = if args.embed_config { Some(serde_json::to_value(&config)?) } else { None };
+
+    // Create the output file up front so a bad path fails before scanning
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) if !args.dry_run => Box::new(
+            fs::File::create(path)
+                .map_err(|e| anyhow::anyhow!("Could not create output file {}: {}", path.display(), e))?,
+        ),
+        _ => Box::new(io::stdout()),
+    };
+    if let Some(dir) = args.output_dir.as_ref().filter(|_| !args.dry_run) {
+        fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Could not create output directory {}: {}", dir.display(), e))?;
+    }
+    // Likewise bind the results server before a long scan, not after
+    #[cfg(feature = "serve")]
+    let listener = match args.serve {
+        Some(addr) if !args.dry_run => Some(
+            tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("Could not listen on {}: {}", addr, e))?,
+        ),
+        _ => None,
+    };
+    #[cfg(feature = "webhook")]
+    let webhook = match &args.webhook {
+        Some(url) if !args.dry_run => Some(clapscan::webhook::Webhook::new(
+            url,
+            args.webhook_token.clone(),
+            Duration::from_millis(args.webhook_timeout_ms),
+        )?),
+        _ => None,
+    };
+
+    let family = if args.ipv4 {
+        IpFamily::V4
+    } else if args.ipv6 {
+        IpFamily::V6
+    } else {
+        IpFamily::Any
+    };
+    let resolve = ResolveOptions {
+        family,
+        all_ips: args.all_ips,
+        dns: args.dns_server.map(|server| DnsResolver::new(server, family)).transpose()?,
+        timeout: Some(Duration::from_millis(args.dns_timeout_ms)),
+    };
+
+    let proxy = match &args.proxy {
+        Some(url) => Some(Proxy::parse(url).await?),
+        None => None,
+    };
+    let probes = match &args.probes {
+        Some(path) => load_probes(path)?,
+        None => Vec::new(),
+    };
+    let mut excluded_hosts = match &args.exclude_hosts {
+        Some(spec) => parse_ip_nets(spec)?,
+        None => Vec::new(),
+    };
+    if let Some(path) = &args.exclude_file {
+        excluded_hosts.extend(load_ip_nets(path)?);
+    }
+
+    let mut hosts: Vec<IpAddr> = Vec::new();
+    let mut remote_names: Vec<String> = Vec::new();
+    let mut ptr_names: HashMap<IpAddr, String> = HashMap::new();
+    // Hostnames the user typed, offered as SNI to the addresses they resolved to
+    let mut target_names: HashMap<IpAddr, String> = HashMap::new();
+    // Interface indexes of link-local targets given as fe80::1%eth0
+    let mut zones: HashMap<IpAddr, u32> = HashMap::new();
+    // IPv6 address of a dual-stack name -> its IPv4 address, with --happy-eyeballs
+    let mut races: HashMap<IpAddr, IpAddr> = HashMap::new();
+    // With a target file, one bad entry is reported instead of ending the run
+    let mut target_errors: Vec<TargetError> = Vec::new();
+    let mut target_failed = |target: &str, e: anyhow::Error| -> anyhow::Result<()> {
+        if args.target_file.is_none() {
+            return Err(e);
+        }
+        warn!("Skipping {}: {}", target, e);
+        target_errors.push(TargetError { target: target.to_string(), error: e.to_string() });
+        Ok(())
+    };
+    for target in &targets {
+        info!("Starting scan of {} ({} ports)...", target, ports.len());
+        let (target, zone) = match split_zone(target) {
+            Ok(split) => split,
+            Err(e) => {
+                target_failed(target, e)?;
+                continue;
+            }
+        };
+        let is_literal = target.contains('/')
+            || target.trim_matches(['[', ']']).parse::<IpAddr>().is_ok()
+            || parse_ip_range(target).is_some();
+        if proxy.as_ref().is_some_and(Proxy::remote_dns) && !is_literal {
+            info!("Leaving {} for the proxy to resolve", target);
+            remote_names.push(target.to_string());
+            continue;
+        }
+        let expanded = if args.happy_eyeballs && !is_literal {
+            resolve_dual_stack(target, &resolve).await.map(|(ip, other)| {
+                if let Some(other) = other {
+                    info!("Racing {} against {} for {}", ip, other, target);
+                    races.insert(ip, other);
+                    target_names.insert(other, target.to_string());
                 }
-                _ => None,
+                vec![ip]
+            })
+        } else {
+            expand_target(target, &resolve).await
+        };
+        let ips = match expanded {
+            Ok(ips) => ips,
+            Err(e) => {
+                target_failed(target, e)?;
+                continue;
+            }
+        };
+        if args.resolve_ptr {
+            ptr_names.extend(reverse_lookup_all(&ips, PTR_TIMEOUT).await);
+        }
+        if let [ip] = ips.as_slice() {
+            match ptr_names.get(ip) {
+                Some(name) => info!("Target IP: {} ({})", ip, name),
+                None => info!("Target IP: {}", ip),
+            }
+        } else {
+            info!("Expanded to {} hosts", ips.len());
+        }
+        if !is_literal {
+            target_names.extend(ips.iter().map(|ip| (*ip, target.to_string())));
+        }
+        if let Some(zone) = zone {
+            zones.extend(ips.iter().map(|ip| (*ip, zone)));
+        }
+        hosts.extend(ips);
+    }
+    if hosts.is_empty() && remote_names.is_empty() && !target_errors.is_empty() {
+        return Err(anyhow::anyhow!("No target could be resolved, {} failed", target_errors.len()));
+    }
+    let hosts_excluded = exclude_hosts(&mut hosts, &mut races, &excluded_hosts);
+    if hosts_excluded > 0 {
+        info!("Excluded {} hosts", hosts_excluded);
+    }
+
+    // Behind a proxy the only direct peer is the proxy itself
+    let peers = match &proxy {
+        Some(proxy) => vec![proxy.addr().ip()],
+        None => hosts.clone(),
+    };
+    let source_address = match &args.interface {
+        Some(name) => {
+            // Take the family the targets need, a mixed list is reported by the check below
+            let family = if !peers.is_empty() && peers.iter().all(|ip| ip.is_ipv6()) {
+                IpFamily::V6
+            } else if peers.iter().all(|ip| ip.is_ipv4()) {
+                IpFamily::V4
+            } else {
+                family
+            };
+            let source = interface_address(name, family)?;
+            info!("Sending probes from {} ({})", source, name);
+            Some(source)
+        }
+        None => args.source_address,
+    };
+    if let Some(source) = source_address {
+        if let Some(ip) = peers.iter().find(|ip| ip.is_ipv4() != source.is_ipv4()) {
+            return Err(anyhow::anyhow!(
+                "Source address {} and target {} are not the same address family",
+                source,
+                ip
+            ));
+        }
+    }
+
+    if args.dry_run {
+        out.write_all(render_dry_run(&hosts, &remote_names, &ports, &ptr_names).as_bytes())?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    // Skip whatever a resumed checkpoint already covers
+    let checkpoint_path = args.checkpoint.clone().or_else(|| args.resume.clone());
+    let checkpoint = match &args.resume {
+        Some(path) => Checkpoint::load(path)?,
+        None => Checkpoint::default(),
+    };
+    let mut done = checkpoint.completed();
+    // Likewise ports an earlier scan found open, unless the checkpoint has them already
+    let known: Vec<Finding> = match &args.known_open {
+        Some(path) => {
+            let protocol = if args.udp { "udp" } else { "tcp" };
+            let planned: HashSet<String> =
+                hosts.iter().map(IpAddr::to_string).chain(remote_names.iter().cloned()).collect();
+            let mut seen = HashSet::new();
+            diff::load_findings(path)?
+                .into_iter()
+                .filter(|f| f.status == "open" && f.protocol == protocol)
+                .filter(|f| planned.contains(&*f.host) && ports.contains(&f.port))
+                .filter(|f| !done.get(&*f.host).is_some_and(|ports| ports.contains(&f.port)))
+                .filter(|f| seen.insert((f.host.clone(), f.port)))
+                .map(|f| Finding { carried_forward: true, ..f })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    for f in &known {
+        done.entry(&f.host).or_default().insert(f.port);
+    }
+    // Hosts already known to serve something need no further probes
+    let found_open: HashSet<&str> = checkpoint
+        .findings
+        .iter()
+        .chain(&known)
+        .filter(|f| args.first_open_only && f.status == "open" && open_filter.accepts(f.banner.as_deref()))
+        .map(|f| &*f.host)
+        .collect();
+    let remaining = |host: &str| -> Vec<u16> {
+        if found_open.contains(host) {
+            return Vec::new();
+        }
+        match done.get(host) {
+            Some(done) => ports.iter().copied().filter(|p| !done.contains(p)).collect(),
+            None => ports.clone(),
+        }
+    };
+    let host_plan: Vec<(IpAddr, Vec<u16>)> = hosts.iter().map(|&ip| (ip, remaining(&ip.to_string()))).collect();
+    let remote_plan: Vec<(&str, Vec<u16>)> = remote_names.iter().map(|n| (n.as_str(), remaining(n))).collect();
+    let total: usize = host_plan.iter().map(|(_, p)| p.len()).sum::<usize>()
+        + remote_plan.iter().map(|(_, p)| p.len()).sum::<usize>();
+    if !checkpoint.findings.is_empty() {
+        info!("Resuming, {} probes already done", checkpoint.findings.len());
+    }
+    if let Some(path) = &args.known_open {
+        info!("Carrying forward {} open ports from {}", known.len(), path.display());
+    }
+
+    // Progress goes to stderr and only when a human is watching
+    let progress = if format == OutputFormat::Text && !args.count_only && io::stderr().is_terminal() {
+        let bar = ProgressBar::new(total as u64);
+        bar.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar:40} {pos}/{len} ports (ETA {eta})")
+                .expect("valid progress template"),
+        );
+        bar
+    } else {
+        ProgressBar::hidden()
+    };
+
+    if args.max_concurrency.is_some() && !args.adaptive_concurrency && args.min_rate.is_none() {
+        return Err(anyhow::anyhow!("--max-concurrency only applies with --adaptive-concurrency or --min-rate"));
+    }
+    if let Some(min_rate) = args.min_rate.filter(|&min_rate| args.rate > 0 && min_rate > args.rate) {
+        return Err(anyhow::anyhow!("--min-rate {} is above --rate {}", min_rate, args.rate));
+    }
+    let mut min_rate = args.min_rate.map(|pps| MinRate {
+        pps,
+        max_concurrency: args.max_concurrency.map_or(args.concurrency * 4, |max| max as usize).max(args.concurrency),
+    });
+    let mut adaptive_concurrency = match args.max_concurrency.map(|max| max as usize) {
+        _ if !args.adaptive_concurrency => None,
+        Some(max) if max < args.min_concurrency as usize => {
+            return Err(anyhow::anyhow!("--max-concurrency cannot be below --min-concurrency"));
+        }
+        max => Some(ConcurrencyBounds {
+            min: args.min_concurrency as usize,
+            max: max.unwrap_or(args.concurrency).max(args.min_concurrency as usize),
+        }),
+    };
+    // Every probe in flight holds a socket, stay within the open file limit
+    let wanted = match (adaptive_concurrency, min_rate) {
+        (Some(bounds), _) => bounds.max,
+        (None, Some(min_rate)) => min_rate.max_concurrency,
+        (None, None) => args.concurrency,
+    };
+    let budget = open_file_budget(wanted);
+    if budget < wanted {
+        warn!(
+            "The open file limit allows about {} simultaneous probes, lowering concurrency from {} (raise it with `ulimit -n`)",
+            budget,
+            wanted
+        );
+        if let Some(bounds) = &mut adaptive_concurrency {
+            bounds.max = budget;
+            bounds.min = bounds.min.min(budget);
+        }
+        if let Some(min_rate) = &mut min_rate {
+            min_rate.max_concurrency = budget;
+        }
+    }
+    let retries = RetryPolicy::new(args.retries);
+    let retries = match &args.retry_policy {
+        Some(spec) => retries.with_overrides(spec)?,
+        None => retries,
+    };
+    let opts = ScanOptions {
+        timeout,
+        port_timeouts,
+        concurrency: args.concurrency.min(budget),
+        adaptive_concurrency,
+        min_rate,
+        udp: args.udp,
+        retries,
+        scope_id: None,
+        race_with: None,
+        rate: Some(args.rate).filter(|&r| r > 0),
+        scan_delay: Some(Duration::from_millis(args.scan_delay_ms)).filter(|d| !d.is_zero()),
+        skip_refused_after: args.skip_refused_host.map(|n| n as usize),
+        first_open_only: Some(open_filter.clone()).filter(|_| args.first_open_only),
+        source: source_address,
+        tcp_nodelay: args.tcp_nodelay,
+        ttl: args.ttl,
+        proxy,
+        banner: !args.no_banner,
+        banner_bytes: args.banner_bytes as usize,
+        capture: args.capture_response.map(|bytes| bytes as usize),
+        capture_timeout: Duration::from_millis(args.capture_timeout_ms),
+        banner_timeout: Duration::from_millis(args.banner_timeout_ms),
+        banner_hex: args.banner_hex,
+        banner_retry: args.banner_retry,
+        tls_probe: args.tls_probe,
+        sni: args.sni.clone(),
+        http_title: args.http_title,
+        http_ports: match &args.http_ports {
+            Some(spec) => parse_ports(spec)?,
+            None => DEFAULT_HTTP_PORTS.to_vec(),
+        },
+        probes: Arc::from(probes),
+        stop: Arc::new(AtomicBool::new(false)),
+    };
+    if args.benchmark {
+        let plan: Vec<HostPlan> = hosts
+            .iter()
+            .map(|&ip| Host::Ip(ip))
+            .chain(remote_names.iter().cloned().map(Host::Name))
+            .map(|host| HostPlan {
+                scope_id: match &host {
+                    Host::Ip(ip) => zones.get(ip).copied(),
+                    Host::Name(_) => None,
+                },
+                race_with: match &host {
+                    Host::Ip(ip) => races.get(ip).copied(),
+                    Host::Name(_) => None,
+                },
+                host,
+                ports: ports.clone(),
+                timeout: None,
+                sni: None,
+            })
+            .collect();
+        return run_benchmark(&plan, &opts).await;
+    }
+    // First Ctrl-C stops new probes and keeps what was found, the second quits outright
+    let stop = opts.stop.clone();
+    let interrupt_handler = tokio::spawn(async move {
+        if signal::ctrl_c().await.is_ok() {
+            warn!("Interrupted, waiting for in-flight probes (Ctrl-C again to quit)");
+            stop.store(true, Ordering::Relaxed);
+            if signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
             }
         }
     });
+    // Findings are filtered and annotated as they arrive so jsonl can stream them
+    let mut results: Vec<Finding> = Vec::new();
+    let mut write_error: Option<io::Error> = None;
+    let mut summary = ScanSummary {
+        hosts_expanded,
+        hosts_excluded,
+        hosts_scanned: hosts.len() + remote_names.len(),
+        port_sample,
+        target_errors,
+        ..ScanSummary::default()
+    };
+    let finding_schema = serde_json::to_value(schemars::schema_for!(Finding))?;
+    let mut validation_errors: Vec<String> = Vec::new();
+    // Open ports are echoed to stderr as found, the chosen format still goes to stdout or the file
+    let live = args.live && !args.quiet;
+    let mut tallies: HashMap<String, HostTally> = HashMap::new();
+    // Whether each host answered anything at all; a refusal is an answer too
+    let mut responded: HashMap<String, bool> = HashMap::new();
+    // Per-host totals for the files written by --output-dir
+    let mut host_summaries: HashMap<String, ScanSummary> = HashMap::new();
+    // Hosts that already reported their open port, with --first-open-only
+    let mut first_open: HashSet<String> = HashSet::new();
+    let mut on_finding = |mut r: Finding| {
+        summary.count(&r);
+        *responded.entry(r.host.to_string()).or_default() |= matches!(r.status.as_str(), "open" | "closed");
+        if args.output_dir.is_some() {
+            host_summaries.entry(r.host.to_string()).or_default().count(&r);
+        }
+        if args.detect_honeypot {
+            tallies.entry(r.host.to_string()).or_default().count(&r);
+        }
+        let shown = match r.status.as_str() {
+            "closed" => args.show_closed,
+            "filtered" => args.show_filtered,
+            _ => true,
+        };
+        if !shown {
+            return;
+        }
+        if args.only_with_banner && r.banner.is_none() {
+            summary.hidden_no_banner += 1;
+            return;
+        }
+        if let Some(re) = &banner_match {
+            if !r.banner.as_deref().is_some_and(|b| re.is_match(b)) {
+                summary.hidden_banner_mismatch += 1;
+                return;
+            }
+        }
+        // Probes in flight when the first reported open port came back may find more
+        if args.first_open_only && r.status == "open" && !first_open.insert(r.host.to_string()) {
+            summary.hidden_after_first_open += 1;
+            return;
+        }
+        r.hostname = r.host.parse().ok().and_then(|ip| ptr_names.get(&ip).cloned());
+        if live && r.status == "open" {
+            progress.suspend(|| eprintln!("{}", render_live(&r)));
+        }
+        if format == OutputFormat::Jsonl && !args.count_only && args.output_dir.is_none() {
+            if args.validate_output {
+                let value = serde_json::to_value(&r).expect("findings always serialize");
+                if let Err(errors) = schema::validate(&value, &finding_schema) {
+                    validation_errors.extend(errors);
+                }
+            }
+            let line = serde_json::to_string(&r).expect("findings always serialize");
+            if let Err(e) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+                write_error.get_or_insert(e);
+            }
+        } else {
+            results.push(r);
+        }
+    };
+    // New findings are appended to what the resumed checkpoint already had
+    let mut checkpoint_writer = match &checkpoint_path {
+        Some(path) => Some(
+            CheckpointWriter::start(path, &checkpoint)
+                .map_err(|e| anyhow::anyhow!("Could not write checkpoint {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+    let replayed = checkpoint.findings.len() + known.len();
+    for r in checkpoint.findings.into_iter().chain(known) {
+        on_finding(r);
+    }
 
-    // Execute with bounded concurrency
-    let results: Vec<Finding> = stream::iter(tasks)
-        .buffer_unordered(args.concurrency)
-        .filter_map(|x| async move { x })
-        .collect()
-        .await;
+    let mut checkpoint_error: Option<io::Error> = None;
+    let mut record = |r: Finding| {
+        if let Some(writer) = &mut checkpoint_writer {
+            if let Err(e) = writer.append(&r) {
+                checkpoint_error.get_or_insert(e);
+            }
+        }
+        on_finding(r);
+    };
+    let started = Instant::now();
+    let mut meta = ScanMeta {
+        id: uuid::Uuid::new_v4().to_string(),
+        started_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        targets: match &args.target_file {
+            Some(path) => path.display().to_string(),
+            None => args.target.clone().unwrap_or_default(),
+        },
+        ports: match (args.top_ports, &args.ports_file) {
+            (Some(n), _) => format!("top {}", n),
+            (None, Some(path)) if !ports_given => path.display().to_string(),
+            (None, Some(path)) => format!("{} + {}", args.ports, path.display()),
+            (None, None) => args.ports.clone(),
+        },
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        config,
+        // Known once the scan is over
+        hosts: BTreeMap::new(),
+    };
+    let report = Mutex::new(ScanReport::default());
+    let scan = async {
+        // RTTs are measured up front, concurrently, so every host joins the same batch
+        let timeouts: Vec<Option<Duration>> = if args.adaptive_timeout {
+            stream::iter(&host_plan)
+                .map(|(ip, ports)| async {
+                    let opts = ScanOptions { scope_id: zones.get(ip).copied(), ..opts.clone() };
+                    Some(adaptive_timeout(*ip, ports, &opts).await)
+                })
+                .buffered(opts.concurrency)
+                .collect()
+                .await
+        } else {
+            vec![None; host_plan.len()]
+        };
+        let plan: Vec<HostPlan> = host_plan
+            .into_iter()
+            .zip(timeouts)
+            .map(|((ip, ports), timeout)| HostPlan {
+                host: Host::Ip(ip),
+                ports,
+                timeout,
+                sni: args.sni.clone().or_else(|| target_names.get(&ip).cloned()),
+                scope_id: zones.get(&ip).copied(),
+                race_with: races.get(&ip).copied(),
+            })
+            .chain(remote_plan.into_iter().map(|(name, ports)| HostPlan {
+                host: Host::Name(name.to_string()),
+                ports,
+                timeout: None,
+                sni: None,
+                scope_id: None,
+                race_with: None,
+            }))
+            .collect();
+        scan_plan(&plan, &opts, &progress, &mut record, &report).await
+    };
+    // The budget covers RTT measurement too, whatever finished in time is kept
+    let truncated = match args.max_runtime {
+        Some(secs) => time::timeout(Duration::from_secs(secs), scan).await.is_err(),
+        None => {
+            scan.await;
+            false
+        }
+    };
+    let report = report.into_inner().expect("scan report lock");
+    progress.finish_and_clear();
+    let interrupted = opts.stop.load(Ordering::Relaxed);
+    let probed = summary.ports_scanned - replayed;
+    summary.finish(started.elapsed(), probed);
+    summary.truncated = truncated;
+    summary.hosts_skipped = report.skipped_hosts;
+    summary.hosts_skipped.sort_by_cached_key(|host| {
+        let ip = host.parse::<IpAddr>().ok();
+        (ip.is_none(), ip, host.clone())
+    });
+    summary.not_scanned = total - probed;
+    summary.hosts_up = responded.values().filter(|&&up| up).count();
+    summary.hosts_down = responded.iter().filter(|(_, up)| !**up).map(|(host, _)| host.clone()).collect();
+    summary.hosts_down.sort_by_cached_key(|host| {
+        let ip = host.parse::<IpAddr>().ok();
+        (ip.is_none(), ip, host.clone())
+    });
+    meta.hosts = responded
+        .iter()
+        .map(|(host, &up)| (host.clone(), if up { HostStatus::Up } else { HostStatus::Unresponsive }))
+        .collect();
+    if !summary.hosts_down.is_empty() {
+        info!("{} hosts did not answer any probe", summary.hosts_down.len());
+    }
+    if args.detect_honeypot {
+        summary.suspected_honeypots =
+            suspect_honeypots(&tallies, args.honeypot_open_percent, args.honeypot_same_banner);
+        for suspect in &summary.suspected_honeypots {
+            warn!("{} looks like a honeypot or tarpit: {}", suspect.host, suspect.reason);
+        }
+    }
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+    // Findings take their final shape before validation, so the report checked
+    // is the one written
+    if args.dedup_by_banner {
+        if let Some(path) = &args.dedup_raw {
+            let mut raw = String::new();
+            for r in &results {
+                raw.push_str(&serde_json::to_string(r)?);
+                raw.push('\n');
+            }
+            fs::write(path, raw).map_err(|e| anyhow::anyhow!("Could not write {}: {}", path.display(), e))?;
+        }
+        let before = results.len();
+        results = dedup_by_banner(results, &target_names);
+        summary.deduplicated = before - results.len();
+    }
+    sort_findings(&mut results, args.sort);
+    if args.validate_output && format == OutputFormat::Json {
+        let report = serde_json::to_value(JsonReport { meta: &meta, summary: &summary, findings: &results })?;
+        let schema = serde_json::to_value(schemars::schema_for!(JsonReport))?;
+        if let Err(errors) = schema::validate(&report, &schema) {
+            validation_errors.extend(errors);
+        }
+    }
+    if !validation_errors.is_empty() {
+        return Err(anyhow::anyhow!("Output does not match the schema:\n  {}", validation_errors.join("\n  ")));
+    }
+    if let Some(e) = checkpoint_error {
+        return Err(anyhow::anyhow!("Could not save checkpoint: {}", e));
+    }
+    if let (Some(path), Some(mut writer)) = (&checkpoint_path, checkpoint_writer) {
+        if interrupted || truncated {
+            writer.sync()?;
+            info!("Checkpoint saved, continue with --resume {}", path.display());
+        } else if path.exists() {
+            drop(writer);
+            // The scan finished, nothing left to resume
+            fs::remove_file(path)?;
+        }
+    }
+    if interrupted {
+        warn!("Scan interrupted, showing partial results ({} of {} probes)", probed, total);
+    } else if truncated {
+        warn!("Scan stopped by --max-runtime, showing partial results ({} of {} probes)", probed, total);
+    }
 
     // Output results
-    if args.json {
-        println!("{}", serde_json::to_string_pretty(&results)?);
+    // Colors only make sense on a terminal, never in files or pipes
+    let color = !args.no_color
+        && args.output.is_none()
+        && io::stdout().is_terminal()
+        && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+    if args.count_only {
+        // Counts what would have been listed, so banner filters narrow it down too
+        let open = results.iter().filter(|r| r.status == "open").count();
+        let count = match format {
+            OutputFormat::Json | OutputFormat::Jsonl => serde_json::json!({ "open": open }).to_string(),
+            _ => open.to_string(),
+        };
+        writeln!(out, "{}", count)?;
+    } else if let Some(dir) = &args.output_dir {
+        for (host, host_summary) in host_summaries.iter_mut() {
+            let probed = host_summary.ports_scanned;
+            host_summary.finish(started.elapsed(), probed);
+            match responded.get(host) {
+                Some(true) => host_summary.hosts_up = 1,
+                _ => host_summary.hosts_down = vec![host.clone()],
+            }
+        }
+        let style = RenderStyle { template: template.as_ref(), color: false, compact: args.compact };
+        let written = write_host_files(dir, format, &meta, &results, &host_summaries, style, args.summary)?;
+        info!("Wrote {} host files to {}", written, dir.display());
     } else {
-        let open_ports_count = results.len();
-        println!("Scan completed! Found {} open ports:", open_ports_count);
-        for r in &results {
-            match &r.banner {
-                Some(b) => println!("{}:{} open | {}", r.host, r.port, b),
-                None => println!("{}:{} open", r.host, r.port),
+        if format == OutputFormat::Text && template.is_none() {
+            out.write_all(meta.render().as_bytes())?;
+        }
+        let style = RenderStyle { template: template.as_ref(), color, compact: args.compact };
+        out.write_all(render_results(format, &meta, &results, &summary, style)?.as_bytes())?;
+    }
+    // A template describes the whole output, nothing is added around its lines
+    if format == OutputFormat::Text && template.is_none() && !args.count_only && args.output_dir.is_none() {
+        if args.summary {
+            out.write_all(render_banner_summary(&results).as_bytes())?;
+        }
+        out.write_all(summary.render().as_bytes())?;
+    }
+    out.flush()?;
+
+    // Delivery is best effort, the exit code stays the scan's
+    #[cfg(feature = "webhook")]
+    if let Some(webhook) = &webhook {
+        let report = serde_json::to_string(&JsonReport { meta: &meta, summary: &summary, findings: &results })?;
+        match webhook.send(report).await {
+            Ok(()) => info!("Posted results to the webhook"),
+            Err(e) => warn!("Could not post results to the webhook: {}", e),
+        }
+    }
+    if interrupted {
+        std::process::exit(130);
+    }
+    // The scan is over, from here on Ctrl-C only stops the results server
+    interrupt_handler.abort();
+    #[cfg(feature = "serve")]
+    if let Some(listener) = listener {
+        info!("Serving results on http://{}/results.json, Ctrl-C to quit", listener.local_addr()?);
+        clapscan::serve::serve(listener, &results).await?;
+    }
+    if args.fail_on_empty && summary.open == 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Fold findings of one hostname's addresses that agree on port, protocol,
+/// state and banner into the entry of the lowest address, listing them all.
+/// Hosts that were not given by name are left alone.
+fn dedup_by_banner(results: Vec<Finding>, names: &HashMap<IpAddr, String>) -> Vec<Finding> {
+    type Key = (String, u16, String, String, Option<String>);
+    let mut groups: Vec<Vec<Finding>> = Vec::new();
+    let mut index: HashMap<Key, usize> = HashMap::new();
+    for r in results {
+        let name = r.host.parse::<IpAddr>().ok().and_then(|ip| names.get(&ip));
+        let Some(name) = name else {
+            groups.push(vec![r]);
+            continue;
+        };
+        let key = (name.clone(), r.port, r.protocol.clone(), r.status.clone(), r.banner.clone());
+        match index.get(&key) {
+            Some(&i) => groups[i].push(r),
+            None => {
+                index.insert(key, groups.len());
+                groups.push(vec![r]);
             }
         }
-        if results.is_empty() {
-            println!("No open ports found");
+    }
+    groups
+        .into_iter()
+        .map(|mut group| {
+            if group.len() == 1 {
+                return group.remove(0);
+            }
+            group.sort_by_key(|r| r.host.parse::<IpAddr>().ok());
+            let addresses = group.iter().map(|r| r.host.to_string()).collect();
+            Finding { addresses: Some(addresses), ..group.remove(0) }
+        })
+        .collect()
+}
+
+fn sort_findings(results: &mut [Finding], key: SortKey) {
+    // Addresses compare numerically, so 10.0.0.9 comes before 10.0.0.10; names after them
+    let host = |f: &Finding| {
+        let ip = f.host.parse::<IpAddr>().ok();
+        (ip.is_none(), ip, f.host.clone())
+    };
+    match key {
+        SortKey::Host => results.sort_by_cached_key(|f| (host(f), f.port, f.protocol.clone())),
+        SortKey::Port => results.sort_by_cached_key(|f| (f.port, host(f), f.protocol.clone())),
+        SortKey::Latency => results.sort_by_cached_key(|f| (f.latency_ms.is_none(), f.latency_ms, host(f), f.port)),
+    }
+}
+
+// Concurrency levels tried by --benchmark, and how often each is run
+const BENCHMARK_LEVELS: &[usize] = &[25, 50, 100, 200, 500, 1000, 2000];
+const BENCHMARK_ROUNDS: usize = 3;
+
+/// Throughput of one --benchmark level
+struct BenchmarkLevel {
+    concurrency: usize,
+    // Median over the rounds
+    ports_per_sec: f64,
+    timeout_rate: f64,
+}
+
+/// Run the plan through the normal scan path at each of [`BENCHMARK_LEVELS`] and
+/// print the results along with a recommended concurrency
+async fn run_benchmark(plan: &[HostPlan], opts: &ScanOptions) -> anyhow::Result<()> {
+    let probes: usize = plan.iter().map(|h| h.ports.len()).sum();
+    let budget = open_file_budget(BENCHMARK_LEVELS[BENCHMARK_LEVELS.len() - 1]);
+    let levels: Vec<usize> = BENCHMARK_LEVELS.iter().copied().filter(|&c| c <= budget).collect();
+    if levels.len() < BENCHMARK_LEVELS.len() {
+        warn!("The open file limit allows about {} simultaneous probes, higher levels are skipped", budget);
+    }
+    let mut results = Vec::new();
+    for &concurrency in &levels {
+        // Fixed concurrency, adaptive limits would blur the comparison
+        let opts = ScanOptions { concurrency, adaptive_concurrency: None, min_rate: None, ..opts.clone() };
+        let mut rates = Vec::new();
+        let mut timed_out = 0;
+        for round in 1..=BENCHMARK_ROUNDS {
+            info!("Concurrency {}, round {} of {}", concurrency, round, BENCHMARK_ROUNDS);
+            let started = Instant::now();
+            scan_plan(
+                plan,
+                &opts,
+                &ProgressBar::hidden(),
+                &mut |f| {
+                    timed_out += matches!(f.status.as_str(), "filtered" | "open|filtered") as usize;
+                },
+                &Mutex::default(),
+            )
+            .await;
+            rates.push(probes as f64 / started.elapsed().as_secs_f64().max(f64::EPSILON));
         }
+        rates.sort_by(f64::total_cmp);
+        results.push(BenchmarkLevel {
+            concurrency,
+            ports_per_sec: rates[rates.len() / 2],
+            timeout_rate: timed_out as f64 / (probes * BENCHMARK_ROUNDS).max(1) as f64,
+        });
     }
 
+    let mut out = format!(
+        "Benchmark: {} hosts, {} probes per round, {} rounds per level\n{:>11}  {:>10}  {:>8}\n",
+        plan.len(),
+        probes,
+        BENCHMARK_ROUNDS,
+        "Concurrency",
+        "Ports/sec",
+        "Timeouts"
+    );
+    for level in &results {
+        out.push_str(&format!(
+            "{:>11}  {:>10.1}  {:>7.1}%\n",
+            level.concurrency,
+            level.ports_per_sec,
+            level.timeout_rate * 100.0
+        ));
+    }
+    if let Some(best) = recommend_concurrency(&results) {
+        out.push_str(&format!("Recommended: --concurrency {}\n", best));
+    }
+    print!("{}", out);
     Ok(())
 }
 
+// Levels timing out this much more often than the lowest one are overloading something
+const BENCHMARK_TIMEOUT_MARGIN: f64 = 0.01;
+// Share of the best throughput a lower concurrency may give up and still be preferred
+const BENCHMARK_GOOD_ENOUGH: f64 = 0.95;
+
+/// The lowest concurrency that gets close to the best throughput without extra timeouts
+fn recommend_concurrency(levels: &[BenchmarkLevel]) -> Option<usize> {
+    let baseline = levels.first()?.timeout_rate;
+    let healthy: Vec<&BenchmarkLevel> =
+        levels.iter().filter(|l| l.timeout_rate <= baseline + BENCHMARK_TIMEOUT_MARGIN).collect();
+    let best = healthy.iter().map(|l| l.ports_per_sec).fold(0.0, f64::max);
+    healthy.iter().find(|l| l.ports_per_sec >= best * BENCHMARK_GOOD_ENOUGH).map(|l| l.concurrency)
+}
+
+// Longer lists are cut short in the dry-run listing
+const DRY_RUN_LIST_MAX: usize = 50;
+
+fn render_dry_run(hosts: &[IpAddr], remote_names: &[String], ports: &[u16], ptr_names: &HashMap<IpAddr, String>) -> String {
+    let mut out = format!("Hosts ({}):\n", hosts.len() + remote_names.len());
+    for ip in hosts.iter().take(DRY_RUN_LIST_MAX) {
+        match ptr_names.get(ip) {
+            Some(name) => out.push_str(&format!("  {} ({})\n", ip, name)),
+            None => out.push_str(&format!("  {}\n", ip)),
+        }
+    }
+    if hosts.len() > DRY_RUN_LIST_MAX {
+        out.push_str(&format!("  ... and {} more\n", hosts.len() - DRY_RUN_LIST_MAX));
+    }
+    for name in remote_names {
+        out.push_str(&format!("  {} (resolved by the proxy)\n", name));
+    }
+
+    out.push_str(&format!("Ports ({}):", ports.len()));
+    if ports.len() > DRY_RUN_LIST_MAX {
+        let low = ports.iter().min().copied().unwrap_or_default();
+        let high = ports.iter().max().copied().unwrap_or_default();
+        out.push_str(&format!(" {} to {}\n", low, high));
+    } else {
+        let list: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+        out.push_str(&format!(" {}\n", list.join(",")));
+    }
+    out.push_str(&format!("Total probes: {}\n", (hosts.len() + remote_names.len()) * ports.len()));
+    out
+}
+
+/// Totals for one run, printed after text output and embedded in JSON output
+#[derive(Serialize, JsonSchema, Default, Debug)]
+struct ScanSummary {
+    /// Hosts generated from ranges and wildcards in the target file
+    hosts_expanded: usize,
+    hosts_excluded: usize,
+    hosts_scanned: usize,
+    /// How much of the port list --sample kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port_sample: Option<PortSample>,
+    ports_scanned: usize,
+    open: usize,
+    closed: usize,
+    filtered: usize,
+    open_filtered: usize,
+    /// Hosts that answered at least one probe, open or refused
+    hosts_up: usize,
+    /// Hosts where every probe timed out, likely down or behind a firewall
+    hosts_down: Vec<String>,
+    /// Hosts given up on after refusing every early probe
+    hosts_skipped: Vec<String>,
+    /// Open ports taken from --known-open instead of probed
+    carried_forward: usize,
+    /// Findings left out by --only-with-banner
+    hidden_no_banner: usize,
+    /// Findings folded into another address's entry by --dedup-by-banner
+    deduplicated: usize,
+    /// Further open ports left out by --first-open-only
+    hidden_after_first_open: usize,
+    /// Findings left out by --banner-match
+    hidden_banner_mismatch: usize,
+    /// Target file entries that could not be parsed or resolved
+    target_errors: Vec<TargetError>,
+    /// Hosts flagged by --detect-honeypot
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    suspected_honeypots: Vec<SuspectHost>,
+    /// Planned probes that never ran because the scan was cut short or a host was skipped
+    not_scanned: usize,
+    /// The scan hit --max-runtime before finishing
+    truncated: bool,
+    elapsed_ms: u64,
+    ports_per_sec: f64,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+struct PortSample {
+    percent: f64,
+    /// Ports kept per host
+    sampled: usize,
+    /// Ports selected before sampling
+    total: usize,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+struct SuspectHost {
+    host: String,
+    reason: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+struct TargetError {
+    target: String,
+    error: String,
+}
+
+impl ScanSummary {
+    fn count(&mut self, finding: &Finding) {
+        self.ports_scanned += 1;
+        self.carried_forward += finding.carried_forward as usize;
+        match finding.status.as_str() {
+            "open" => self.open += 1,
+            "closed" => self.closed += 1,
+            "filtered" => self.filtered += 1,
+            _ => self.open_filtered += 1,
+        }
+    }
+
+    // The rate only covers probes sent by this run, not ones replayed from a checkpoint
+    fn finish(&mut self, elapsed: Duration, probed: usize) {
+        self.elapsed_ms = elapsed.as_millis() as u64;
+        let secs = elapsed.as_secs_f64();
+        self.ports_per_sec = if secs > 0.0 { (probed as f64 / secs * 10.0).round() / 10.0 } else { 0.0 };
+    }
+
+    fn render(&self) -> String {
+        let mut out = format!(
+            "\nScanned {} ports in {:.2}s ({} ports/sec): {} open, {} closed, {} filtered",
+            self.ports_scanned,
+            self.elapsed_ms as f64 / 1000.0,
+            self.ports_per_sec,
+            self.open,
+            self.closed,
+            self.filtered
+        );
+        if self.open_filtered > 0 {
+            out.push_str(&format!(", {} open|filtered", self.open_filtered));
+        }
+        if self.hosts_expanded > 0 {
+            out.push_str(&format!(", {} hosts from target ranges", self.hosts_expanded));
+        }
+        if self.hosts_excluded > 0 {
+            out.push_str(&format!(", {} hosts excluded", self.hosts_excluded));
+        }
+        if let Some(sample) = &self.port_sample {
+            out.push_str(&format!(", sampled {} of {} ports ({}%)", sample.sampled, sample.total, sample.percent));
+        }
+        if self.carried_forward > 0 {
+            out.push_str(&format!(", {} carried forward from --known-open", self.carried_forward));
+        }
+        if self.hidden_no_banner > 0 {
+            out.push_str(&format!(", {} without banner hidden", self.hidden_no_banner));
+        }
+        if self.hidden_after_first_open > 0 {
+            out.push_str(&format!(", {} more open hidden by --first-open-only", self.hidden_after_first_open));
+        }
+        if self.deduplicated > 0 {
+            out.push_str(&format!(", {} merged by --dedup-by-banner", self.deduplicated));
+        }
+        if self.hidden_banner_mismatch > 0 {
+            out.push_str(&format!(", {} not matching --banner-match hidden", self.hidden_banner_mismatch));
+        }
+        if self.not_scanned > 0 {
+            out.push_str(&format!(", {} not scanned", self.not_scanned));
+        }
+        if self.truncated {
+            out.push_str(" (truncated by --max-runtime)");
+        }
+        out.push('\n');
+        if !self.hosts_skipped.is_empty() {
+            out.push_str(&format!("Skipped after refusals: {}\n", self.hosts_skipped.join(", ")));
+        }
+        if !self.hosts_down.is_empty() {
+            let mut listed = self.hosts_down.iter().take(HOSTS_DOWN_LIST_MAX).cloned().collect::<Vec<_>>().join(", ");
+            if self.hosts_down.len() > HOSTS_DOWN_LIST_MAX {
+                listed.push_str(&format!(" and {} more", self.hosts_down.len() - HOSTS_DOWN_LIST_MAX));
+            }
+            out.push_str(&format!(
+                "Hosts: {} up, {} unresponsive (every probe timed out): {}\n",
+                self.hosts_up,
+                self.hosts_down.len(),
+                listed
+            ));
+        }
+        if !self.suspected_honeypots.is_empty() {
+            out.push_str(&format!("Likely honeypots or tarpits ({}):\n", self.suspected_honeypots.len()));
+            for suspect in &self.suspected_honeypots {
+                out.push_str(&format!("  {}: {}\n", suspect.host, suspect.reason));
+            }
+        }
+        if !self.target_errors.is_empty() {
+            out.push_str(&format!("Failed targets ({}):\n", self.target_errors.len()));
+            for failure in &self.target_errors {
+                out.push_str(&format!("  {}: {}\n", failure.target, failure.error));
+            }
+        }
+        out
+    }
+}
+
+// Longer lists of unresponsive hosts are cut short in text output
+const HOSTS_DOWN_LIST_MAX: usize = 20;
+
+// Hosts with fewer ports scanned say too little for the open ratio to mean anything
+const HONEYPOT_MIN_PORTS: usize = 20;
+
+/// What --detect-honeypot knows about one host
+#[derive(Default)]
+struct HostTally {
+    scanned: usize,
+    open: usize,
+    // Open ports per normalized banner
+    banners: HashMap<String, usize>,
+}
+
+impl HostTally {
+    fn count(&mut self, finding: &Finding) {
+        self.scanned += 1;
+        if finding.status != "open" {
+            return;
+        }
+        self.open += 1;
+        if let Some(banner) = finding.banner_key() {
+            *self.banners.entry(banner.to_string()).or_default() += 1;
+        }
+    }
+}
+
+fn suspect_honeypots(tallies: &HashMap<String, HostTally>, open_percent: f64, same_banner: usize) -> Vec<SuspectHost> {
+    let mut suspects: Vec<SuspectHost> = tallies
+        .iter()
+        .filter_map(|(host, tally)| {
+            let percent = tally.open as f64 * 100.0 / tally.scanned as f64;
+            let repeated = tally.banners.values().copied().max().unwrap_or(0);
+            let reason = if tally.scanned >= HONEYPOT_MIN_PORTS && percent >= open_percent {
+                format!("{} of {} ports open ({:.0}%)", tally.open, tally.scanned, percent)
+            } else if repeated >= same_banner {
+                format!("{} open ports return the same banner", repeated)
+            } else {
+                return None;
+            };
+            Some(SuspectHost { host: host.clone(), reason })
+        })
+        .collect();
+    suspects.sort_by(|a, b| a.host.cmp(&b.host));
+    suspects
+}
+
+/// Identifies a run, so stored results say where they came from
+#[derive(Serialize, JsonSchema, Debug)]
+struct ScanMeta {
+    /// Random UUID, unique per run
+    id: String,
+    /// When the scan started, ISO 8601 in UTC
+    started_at: String,
+    /// The target or target file as given
+    targets: String,
+    /// The port spec as given
+    ports: String,
+    /// ClapScan version
+    version: String,
+    /// Effective options, with --embed-config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<serde_json::Value>,
+    /// Whether each scanned host answered at least one probe, open or refused
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    hosts: BTreeMap<String, HostStatus>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum HostStatus {
+    Up,
+    /// Every probe timed out, likely down or behind a firewall
+    Unresponsive,
+}
+
+impl ScanMeta {
+    fn render(&self) -> String {
+        format!(
+            "ClapScan {} scan {} started {}, targets {}, ports {}\n",
+            self.version, self.id, self.started_at, self.targets, self.ports
+        )
+    }
+}
+
+/// Options a run actually used, for --print-config and --embed-config
+#[derive(Serialize)]
+struct EffectiveConfig<'a> {
+    options: &'a Args,
+    /// --output-format, or json with --json
+    output_format: OutputFormat,
+    /// Ports per host after -p, --top-ports, --ports-file and --exclude-ports
+    port_count: usize,
+    /// Targets after expanding target file ranges, before DNS and CIDR expansion
+    target_count: usize,
+    probe_count: usize,
+}
+
+// Keep proxy passwords out of printed and embedded configs
+fn serialize_redacted_proxy<S: serde::Serializer>(proxy: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let redacted = proxy.as_deref().map(|url| match url.split_once("://") {
+        Some((scheme, rest)) => match rest.rsplit_once('@') {
+            Some((auth, host)) => {
+                let user = auth.split_once(':').map_or(auth, |(user, _)| user);
+                format!("{}://{}:***@{}", scheme, user, host)
+            }
+            None => url.to_string(),
+        },
+        None => url.to_string(),
+    });
+    redacted.serialize(serializer)
+}
+
+#[cfg(feature = "webhook")]
+fn serialize_redacted_secret<S: serde::Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    secret.as_ref().map(|_| "***").serialize(serializer)
+}
+
+/// The document written by --output-format json
+#[derive(Serialize, JsonSchema)]
+struct JsonReport<'a> {
+    meta: &'a ScanMeta,
+    summary: &'a ScanSummary,
+    findings: &'a [Finding],
+}
+
+/// How findings are laid out, beyond the choice of format
+#[derive(Clone, Copy)]
+struct RenderStyle<'a> {
+    /// --format-template, text only
+    template: Option<&'a Template>,
+    color: bool,
+    /// --compact, JSON only
+    compact: bool,
+}
+
+fn to_json<T: Serialize>(value: &T, compact: bool) -> serde_json::Result<String> {
+    if compact {
+        serde_json::to_string(value)
+    } else {
+        serde_json::to_string_pretty(value)
+    }
+}
+
+fn render_results(
+    format: OutputFormat,
+    meta: &ScanMeta,
+    results: &[Finding],
+    summary: &ScanSummary,
+    style: RenderStyle,
+) -> anyhow::Result<String> {
+    Ok(match format {
+        OutputFormat::Json => format!("{}\n", to_json(&JsonReport { meta, summary, findings: results }, style.compact)?),
+        OutputFormat::Csv => render_csv(results),
+        OutputFormat::Text => match style.template {
+            Some(template) => results.iter().map(|r| template.render(r) + "\n").collect(),
+            None => render_text(results, style.color),
+        },
+        OutputFormat::Grepable => render_grepable(results),
+        OutputFormat::Ports => render_ports(results, summary.hosts_scanned > 1),
+        OutputFormat::Xml => render_xml(results),
+        OutputFormat::Prometheus => render_prometheus(results, summary),
+        // Already streamed while scanning
+        OutputFormat::Jsonl => String::new(),
+    })
+}
+
+// Host names may come from a target file, keep only characters safe in any file name
+fn host_file_name(host: &str, format: OutputFormat) -> String {
+    let stem: String = host
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}.{}", stem.trim_start_matches('.'), format.extension())
+}
+
+/// Write `<host>.<ext>` into `dir` for every scanned host, returning how many files were written
+fn write_host_files(
+    dir: &Path,
+    format: OutputFormat,
+    meta: &ScanMeta,
+    results: &[Finding],
+    host_summaries: &HashMap<String, ScanSummary>,
+    style: RenderStyle,
+    banner_summary: bool,
+) -> anyhow::Result<usize> {
+    let mut by_host: HashMap<&str, Vec<Finding>> = HashMap::new();
+    for r in results {
+        by_host.entry(&*r.host).or_default().push(r.clone());
+    }
+    for (host, summary) in host_summaries {
+        let findings = by_host.remove(host.as_str()).unwrap_or_default();
+        let content = match format {
+            OutputFormat::Jsonl => findings
+                .iter()
+                .map(|r| serde_json::to_string(r).map(|line| line + "\n"))
+                .collect::<Result<String, _>>()?,
+            OutputFormat::Text if style.template.is_some() => render_results(format, meta, &findings, summary, style)?,
+            OutputFormat::Text => {
+                let mut content = meta.render();
+                content.push_str(&render_results(format, meta, &findings, summary, style)?);
+                if banner_summary {
+                    content.push_str(&render_banner_summary(&findings));
+                }
+                content.push_str(&summary.render());
+                content
+            }
+            _ => render_results(format, meta, &findings, summary, style)?,
+        };
+        let path = dir.join(host_file_name(host, format));
+        fs::write(&path, content)
+            .map_err(|e| anyhow::anyhow!("Could not write output file {}: {}", path.display(), e))?;
+    }
+    Ok(host_summaries.len())
+}
+
+fn render_live(r: &Finding) -> String {
+    let host = if r.host.contains(':') { format!("[{}]", r.host) } else { r.host.to_string() };
+    let mut line = format!("Found {}:{}/{}", host, r.port, r.protocol);
+    match (&r.service, &r.version) {
+        (Some(s), Some(v)) => line.push_str(&format!(" [{} {}]", s, v)),
+        (Some(s), None) => line.push_str(&format!(" [{}]", s)),
+        _ => {}
+    }
+    if let Some(b) = &r.banner {
+        line.push_str(&format!(" | {}", b));
+    }
+    line
+}
+
+fn render_text(results: &[Finding], color: bool) -> String {
+    let mut out = String::new();
+    let open_ports_count = results.iter().filter(|r| r.status == "open").count();
+    out.push_str(&format!("Scan completed! Found {} open ports:\n", open_ports_count));
+
+    let rows: Vec<[String; 4]> = results
+        .iter()
+        .map(|r| {
+            // Bracket IPv6 hosts so the port separator stays unambiguous
+            let host = if r.host.contains(':') { format!("[{}]", r.host) } else { r.host.to_string() };
+            // A carried forward latency was measured by another scan
+            let latency = match r.latency_ms {
+                _ if r.carried_forward => "(known)".to_string(),
+                Some(ms) => format!("({}ms)", ms),
+                None => String::new(),
+            };
+            let service = match (&r.service, &r.version) {
+                (Some(s), Some(v)) => format!("[{} {}]", s, v),
+                (Some(s), None) => format!("[{}]", s),
+                _ => String::new(),
+            };
+            [format!("{}:{}", host, r.port), r.status.clone(), latency, service]
+        })
+        .collect();
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    for (r, [endpoint, status, latency, service]) in results.iter().zip(&rows) {
+        let painted = match (color, status.as_str()) {
+            (false, _) => status.clone(),
+            (true, "open") => status.green().bold().to_string(),
+            (true, "open|filtered") => status.yellow().to_string(),
+            (true, _) => status.dimmed().to_string(),
+        };
+        // Pad the plain text, escape codes would throw the widths off
+        let mut line = format!("{:<w$} {}{}", endpoint, painted, " ".repeat(widths[1] - status.len()), w = widths[0]);
+        for (cell, width) in [(latency, widths[2]), (service, widths[3])] {
+            if width > 0 {
+                line.push_str(&format!(" {:<w$}", cell, w = width));
+            }
+        }
+        match &r.banner {
+            Some(b) if color => line.push_str(&format!(" | {}", b.yellow())),
+            Some(b) => line.push_str(&format!(" | {}", b)),
+            None => line.truncate(line.trim_end().len()),
+        }
+        out.push_str(&line);
+        out.push('\n');
+        if let Some(addresses) = &r.addresses {
+            out.push_str(&format!("    also on {}\n", addresses[1..].join(", ")));
+        }
+    }
+    if open_ports_count == 0 {
+        out.push_str("No open ports found\n");
+    }
+    out
+}
+
+fn render_diff(changes: &[HostDiff]) -> String {
+    if changes.is_empty() {
+        return "No changes\n".to_string();
+    }
+    let banner = |b: &Option<String>| b.as_deref().map(|b| format!(" {}", b)).unwrap_or_default();
+    let mut out = String::new();
+    for host in changes {
+        out.push_str(&format!("{}\n", host.host));
+        for p in &host.opened {
+            out.push_str(&format!("  + {}/{}{}\n", p.port, p.protocol, banner(&p.banner)));
+        }
+        for p in &host.closed {
+            out.push_str(&format!("  - {}/{}{}\n", p.port, p.protocol, banner(&p.banner)));
+        }
+        for c in &host.changed {
+            out.push_str(&format!(
+                "  ~ {}/{} {} -> {}\n",
+                c.port,
+                c.protocol,
+                c.old.as_deref().unwrap_or("(no banner)"),
+                c.new.as_deref().unwrap_or("(no banner)")
+            ));
+        }
+    }
+    out
+}
+
+fn render_banner_summary(results: &[Finding]) -> String {
+    let mut clusters: HashMap<String, Vec<(String, u16)>> = HashMap::new();
+    for r in results {
+        if let Some(key) = r.banner_key() {
+            if !key.is_empty() {
+                clusters.entry(key.to_string()).or_default().push((r.host.to_string(), r.port));
+            }
+        }
+    }
+    let mut clusters: Vec<(String, Vec<(String, u16)>)> = clusters.into_iter().collect();
+    clusters.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+
+    let mut out = String::from("\nBanner summary:\n");
+    if clusters.is_empty() {
+        out.push_str("  No banners collected\n");
+    }
+    for (banner, endpoints) in clusters {
+        out.push_str(&format!("  {} x {}\n", endpoints.len(), banner));
+        let endpoints: Vec<String> = endpoints
+            .iter()
+            .map(|(host, port)| {
+                if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) }
+            })
+            .collect();
+        out.push_str(&format!("      {}\n", endpoints.join(", ")));
+    }
+    out
+}
+
+// nmap-style grepable output, one line per host
+fn render_grepable(results: &[Finding]) -> String {
+    let mut hosts: Vec<(&str, Vec<&Finding>)> = Vec::new();
+    for r in results {
+        match hosts.iter_mut().find(|(host, _)| *host == &*r.host) {
+            Some((_, findings)) => findings.push(r),
+            None => hosts.push((&r.host, vec![r])),
+        }
+    }
+
+    let mut out = String::new();
+    for (host, mut findings) in hosts {
+        findings.sort_by_key(|r| r.port);
+        let ports: Vec<String> = findings
+            .iter()
+            .map(|r| {
+                let service = service_name(r.port).unwrap_or("");
+                format!("{}/{}/{}//{}///", r.port, r.status, r.protocol, service)
+            })
+            .collect();
+        out.push_str(&format!("Host: {} ()\tPorts: {}\n", host, ports.join(", ")));
+    }
+    out
+}
+
+fn render_ports(results: &[Finding], per_host: bool) -> String {
+    let mut hosts: Vec<(&str, Vec<u16>)> = Vec::new();
+    for r in results.iter().filter(|r| r.status == "open") {
+        match hosts.iter_mut().find(|(host, _)| *host == &*r.host) {
+            Some((_, ports)) => ports.push(r.port),
+            None => hosts.push((&r.host, vec![r.port])),
+        }
+    }
+    let line = |ports: &mut Vec<u16>| {
+        ports.sort_unstable();
+        ports.dedup();
+        ports.iter().map(u16::to_string).collect::<Vec<_>>().join(",")
+    };
+    if !per_host {
+        let mut ports: Vec<u16> = hosts.into_iter().flat_map(|(_, ports)| ports).collect();
+        return format!("{}\n", line(&mut ports));
+    }
+    hosts.into_iter().map(|(host, mut ports)| format!("{}: {}\n", host, line(&mut ports))).collect()
+}
+
+// nmap's XML layout, enough for parsers like python-libnmap and nmap-parser
+fn render_xml(results: &[Finding]) -> String {
+    let mut hosts: Vec<(&str, Vec<&Finding>)> = Vec::new();
+    for r in results {
+        match hosts.iter_mut().find(|(host, _)| *host == &*r.host) {
+            Some((_, findings)) => findings.push(r),
+            None => hosts.push((&r.host, vec![r])),
+        }
+    }
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<nmaprun scanner=\"clapscan\" version=\"{}\" xmloutputversion=\"1.05\">\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    for (host, mut findings) in hosts {
+        findings.sort_by_key(|r| r.port);
+        out.push_str("<host>\n<status state=\"up\" reason=\"user-set\"/>\n");
+        // Names resolved by the proxy have no address of their own
+        let ip = host.parse::<IpAddr>().ok();
+        if let Some(ip) = ip {
+            let addrtype = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+            out.push_str(&format!("<address addr=\"{}\" addrtype=\"{}\"/>\n", ip, addrtype));
+        }
+        let names: Vec<(&str, &str)> = match (ip, findings[0].hostname.as_deref()) {
+            (None, _) => vec![(host, "user")],
+            (Some(_), Some(ptr)) => vec![(ptr, "PTR")],
+            (Some(_), None) => Vec::new(),
+        };
+        out.push_str("<hostnames>");
+        for (name, kind) in names {
+            out.push_str(&format!("<hostname name=\"{}\" type=\"{}\"/>", xml_escape(name), kind));
+        }
+        out.push_str("</hostnames>\n<ports>\n");
+        for r in findings {
+            let reason = match r.status.as_str() {
+                _ if r.carried_forward => "user-set",
+                "open" if r.protocol == "udp" => "udp-response",
+                "open" => "syn-ack",
+                "closed" if r.protocol == "udp" => "port-unreach",
+                "closed" => "conn-refused",
+                _ => "no-response",
+            };
+            out.push_str(&format!(
+                "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\"/>",
+                r.protocol, r.port, r.status, reason
+            ));
+            match (&r.service, service_name(r.port)) {
+                (Some(product), name) => {
+                    out.push_str(&format!(
+                        "<service name=\"{}\" product=\"{}\"",
+                        xml_escape(name.unwrap_or(product)),
+                        xml_escape(product)
+                    ));
+                    if let Some(version) = &r.version {
+                        out.push_str(&format!(" version=\"{}\"", xml_escape(version)));
+                    }
+                    out.push_str(" method=\"probed\" conf=\"10\"/>");
+                }
+                (None, Some(name)) => {
+                    out.push_str(&format!("<service name=\"{}\" method=\"table\" conf=\"3\"/>", name));
+                }
+                (None, None) => {}
+            }
+            if let Some(banner) = &r.banner {
+                out.push_str(&format!("<script id=\"banner\" output=\"{}\"/>", xml_escape(banner)));
+            }
+            out.push_str("</port>\n");
+        }
+        out.push_str("</ports>\n</host>\n");
+    }
+    out.push_str("</nmaprun>\n");
+    out
+}
+
+// Escape for both attribute values and text, dropping characters XML 1.0 forbids
+fn xml_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push_str(&format!("&#{};", c as u32)),
+            c if (c as u32) < 0x20 || c == '\u{fffe}' || c == '\u{ffff}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn render_prometheus(results: &[Finding], summary: &ScanSummary) -> String {
+    let mut out = String::new();
+    out.push_str("# HELP clapscan_port_open Whether the port was found open (1) or not (0).\n");
+    out.push_str("# TYPE clapscan_port_open gauge\n");
+    for r in results {
+        out.push_str(&format!(
+            "clapscan_port_open{{host=\"{}\",port=\"{}\",protocol=\"{}\"}} {}\n",
+            prometheus_label(&r.host),
+            r.port,
+            r.protocol,
+            u8::from(r.status == "open")
+        ));
+    }
+    out.push_str("# HELP clapscan_open_ports_total Number of open ports found by the last scan.\n");
+    out.push_str("# TYPE clapscan_open_ports_total gauge\n");
+    out.push_str(&format!("clapscan_open_ports_total {}\n", summary.open));
+    out.push_str("# HELP clapscan_scan_duration_seconds Wall time of the last scan.\n");
+    out.push_str("# TYPE clapscan_scan_duration_seconds gauge\n");
+    out.push_str(&format!("clapscan_scan_duration_seconds {}\n", summary.elapsed_ms as f64 / 1000.0));
+    out
+}
+
+// Label values escape backslash, double quote and line feed
+fn prometheus_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn render_csv(results: &[Finding]) -> String {
+    let mut out = String::from("host,port,status,banner\r\n");
+    for r in results {
+        out.push_str(&format!(
+            "{},{},{},{}\r\n",
+            csv_field(&r.host),
+            r.port,
+            csv_field(&r.status),
+            csv_field(r.banner.as_deref().unwrap_or(""))
+        ));
+    }
+    out
+}
+
+// RFC 4180 quoting: wrap fields containing separators, quotes or line breaks
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 async fn install_to_path() -> anyhow::Result<()> {
     println!("Installing ClapScan to PATH...");
     
@@ -128,9 +2350,7 @@ async fn install_to_path() -> anyhow::Result<()> {
     let current_exe = env::current_exe()?;
     
     // Get user's bin directory
-    let user_dirs = UserDirs::new().ok_or_else(|| anyhow::anyhow!("Could not find user directories"))?;
-    let home_dir = user_dirs.home_dir();
-    let bin_dir = home_dir.join("bin");
+    let (bin_dir, target_path) = install_paths()?;
     
     // Create bin directory if doesn't exist
     if !bin_dir.exists() {
@@ -139,24 +2359,48 @@ async fn install_to_path() -> anyhow::Result<()> {
     }
      
     // Copy executable to bin directory
-    let target_path = bin_dir.join("clapscan");
     fs::copy(&current_exe, &target_path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&target_path, fs::Permissions::from_mode(0o755))?;
+    }
     
     println!("ClapScan installed successfully!");
     println!("Location: {}", target_path.display());
+    
+    // Warn when the shell won't find the binary
+    let on_path = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir == bin_dir))
+        .unwrap_or(false);
+    if !on_path {
+        println!("Warning: {} is not in your PATH, add it to run clapscan from anywhere", bin_dir.display());
+    }
     println!("Example: clapscan google.com -p 80,443");
-    println!("");
+    println!();
     println!("To uninstall, run: clapscan --uninstall");
     
     Ok(())
 }
 
+// Per-OS user bin directory and the installed binary inside it
+fn install_paths() -> anyhow::Result<(PathBuf, PathBuf)> {
+    let user_dirs = UserDirs::new().ok_or_else(|| anyhow::anyhow!("Could not find user directories"))?;
+    let home_dir = user_dirs.home_dir();
+    let bin_dir = if cfg!(windows) {
+        home_dir.join("bin")
+    } else {
+        home_dir.join(".local").join("bin")
+    };
+    let exe_name = if cfg!(windows) { "clapscan.exe" } else { "clapscan" };
+    let target_path = bin_dir.join(exe_name);
+    Ok((bin_dir, target_path))
+}
+
 async fn uninstall_from_path() -> anyhow::Result<()> {
     println!("Uninstalling ClapScan from PATH...");
     
-    let user_dirs = UserDirs::new().ok_or_else(|| anyhow::anyhow!("Could not find user directories"))?;
-    let home_dir = user_dirs.home_dir();
-    let target_path = home_dir.join("bin").join("clapscan.exe");
+    let (_, target_path) = install_paths()?;
     
     if target_path.exists() {
         fs::remove_file(&target_path)?;
@@ -167,40 +2411,3 @@ async fn uninstall_from_path() -> anyhow::Result<()> {
     
     Ok(())
 }
-
-fn parse_ports(spec: &str) -> anyhow::Result<Vec<u16>> {
-    let mut ports = Vec::new();
-    for part in spec.split(',') {
-        let p = part.trim();
-        if p.contains('-') {
-            let (a, b) = p.split_once('-').ok_or_else(|| anyhow::anyhow!("bad port range"))?;
-            let a: u16 = a.trim().parse()?;
-            let b: u16 = b.trim().parse()?;
-            let start = a.min(b);
-            let end = a.max(b);
-            for port in start..=end {
-                ports.push(port);
-            }
-        } else {
-            ports.push(p.parse()?);
-        }
-    }
-    ports.sort_unstable();
-    ports.dedup();
-    Ok(ports)
-}
-
-async fn resolve_host(host: &str) -> anyhow::Result<std::net::IpAddr> {
-    // Try to parse as IP first
-    if let Ok(ip) = host.parse::<std::net::IpAddr>() {
-        return Ok(ip);
-    }
-    
-    // DNS lookup
-    let addrs = tokio::net::lookup_host(format!("{}:0", host)).await?;
-    for addr in addrs {
-        return Ok(addr.ip());
-    }
-    
-    Err(anyhow::anyhow!("Failed to resolve host: {}", host))
-}
\ No newline at end of file
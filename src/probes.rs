@@ -0,0 +1,107 @@
+use crate::fingerprint::{self, Fingerprint, Rule};
+use crate::parse_ports;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// A user defined probe: what to send to some ports and how to label the reply
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub name: String,
+    pub ports: Vec<u16>,
+    pub udp: bool,
+    pub payload: Vec<u8>,
+    pub rule: Option<Rule>,
+}
+
+impl Probe {
+    /// Label a banner this probe elicited, if its pattern matches
+    pub fn identify(&self, banner: &str) -> Option<Fingerprint> {
+        fingerprint::fingerprint_with(banner, self.rule.as_slice())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProbeFile {
+    #[serde(default)]
+    probe: Vec<ProbeEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProbeEntry {
+    name: String,
+    ports: String,
+    #[serde(default)]
+    protocol: Protocol,
+    #[serde(default)]
+    send: String,
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    service: Option<String>,
+}
+
+#[derive(Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Load probe definitions from a TOML file of `[[probe]]` tables
+pub fn load_probes(path: &Path) -> anyhow::Result<Vec<Probe>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Could not read probes file {}: {}", path.display(), e))?;
+    let file: ProbeFile =
+        toml::from_str(&content).map_err(|e| anyhow::anyhow!("Invalid probes file {}: {}", path.display(), e))?;
+
+    file.probe
+        .into_iter()
+        .map(|entry| {
+            let context = |e: anyhow::Error| anyhow::anyhow!("{}: probe '{}': {}", path.display(), entry.name, e);
+            let ports = parse_ports(&entry.ports).map_err(context)?;
+            let payload = unescape(&entry.send).map_err(context)?;
+            let rule = match &entry.pattern {
+                Some(pattern) => {
+                    let service = entry.service.as_deref().unwrap_or(&entry.name);
+                    Some(Rule::new(service, pattern).map_err(|e| context(e.into()))?)
+                }
+                None => None,
+            };
+            Ok(Probe { name: entry.name.clone(), ports, udp: entry.protocol == Protocol::Udp, payload, rule })
+        })
+        .collect()
+}
+
+// Decode \xHH, \r, \n, \t, \0 and \\ so payloads can carry arbitrary bytes
+fn unescape(s: &str) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or_else(|| anyhow::anyhow!("invalid escape \\x{} in send", hex))?;
+                out.push(byte);
+            }
+            Some('r') => out.push(b'\r'),
+            Some('n') => out.push(b'\n'),
+            Some('t') => out.push(b'\t'),
+            Some('0') => out.push(0),
+            Some('\\') => out.push(b'\\'),
+            Some(other) => return Err(anyhow::anyhow!("unknown escape \\{} in send", other)),
+            None => return Err(anyhow::anyhow!("send ends with a lone backslash")),
+        }
+    }
+    Ok(out)
+}
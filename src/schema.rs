@@ -0,0 +1,100 @@
+use serde_json::Value;
+
+/// Check `value` against a JSON schema as produced by schemars. Only the
+/// subset schemars emits for ClapScan's types is understood: `$ref` into
+/// `$defs`, `type`, `properties`, `required`, `items` and `anyOf`. Unknown
+/// object keys are reported too, so a field missing from the schema shows up.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    check(value, schema, schema, "$", &mut errors);
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+fn check(value: &Value, schema: &Value, root: &Value, path: &str, errors: &mut Vec<String>) {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let target = reference
+            .strip_prefix("#/")
+            .and_then(|pointer| root.pointer(&format!("/{}", pointer)));
+        match target {
+            Some(target) => check(value, target, root, path, errors),
+            None => errors.push(format!("{}: unresolved schema reference {}", path, reference)),
+        }
+        return;
+    }
+    if let Some(variants) = schema.get("anyOf").and_then(Value::as_array) {
+        let matches_one = variants.iter().any(|variant| {
+            let mut scratch = Vec::new();
+            check(value, variant, root, path, &mut scratch);
+            scratch.is_empty()
+        });
+        if !matches_one {
+            errors.push(format!("{}: does not match any allowed shape", path));
+        }
+        return;
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.iter().any(|t| has_type(value, t)) {
+            errors.push(format!("{}: expected {}, found {}", path, allowed.join(" or "), type_name(value)));
+            return;
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !map.contains_key(key) {
+                        errors.push(format!("{}: missing required field {}", path, key));
+                    }
+                }
+            }
+            if let Some(properties) = properties {
+                for (key, field) in map {
+                    match properties.get(key) {
+                        Some(field_schema) => check(field, field_schema, root, &format!("{}.{}", path, key), errors),
+                        None => errors.push(format!("{}: field {} is not in the schema", path, key)),
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item, item_schema, root, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
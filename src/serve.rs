@@ -0,0 +1,21 @@
+use crate::Finding;
+use axum::http::header;
+use axum::{routing::get, Router};
+use std::io;
+use tokio::net::TcpListener;
+use tokio::signal;
+
+/// Serve `findings` at `/results.json`, plus a `/healthz` liveness check,
+/// until Ctrl-C is pressed
+pub async fn serve(listener: TcpListener, findings: &[Finding]) -> io::Result<()> {
+    // Results never change once the scan is done, so they are encoded once
+    let body = serde_json::to_string(findings)?;
+    let app = Router::new()
+        .route("/results.json", get(|| async { ([(header::CONTENT_TYPE, "application/json")], body) }))
+        .route("/healthz", get(|| async { "ok\n" }));
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = signal::ctrl_c().await;
+        })
+        .await
+}
@@ -0,0 +1,91 @@
+use crate::Finding;
+
+/// Placeholders a [`Template`] can expand
+pub const FIELDS: &[&str] = &["host", "port", "protocol", "status", "banner", "latency", "service", "version"];
+
+/// A line layout for findings, like "{host}:{port} {status} {banner}".
+/// `{{` and `}}` stand for literal braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+}
+
+impl Template {
+    /// Parse a template, rejecting unknown placeholders and unbalanced braces
+    pub fn parse(template: &str) -> anyhow::Result<Template> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(anyhow::anyhow!("Unclosed '{{{}' in template", name)),
+                        }
+                    }
+                    let field = FIELDS.iter().find(|f| **f == name).ok_or_else(|| {
+                        anyhow::anyhow!("Unknown placeholder {{{}}} in template, expected one of {}", name, list(FIELDS))
+                    })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err(anyhow::anyhow!("Unmatched '}}' in template, write '}}}}' for a literal brace")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Expand the template for one finding; missing values expand to nothing
+    pub fn render(&self, finding: &Finding) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(field) => out.push_str(&field_value(finding, field)),
+            }
+        }
+        out
+    }
+}
+
+fn field_value(f: &Finding, field: &str) -> String {
+    match field {
+        "host" => f.host.to_string(),
+        "port" => f.port.to_string(),
+        "protocol" => f.protocol.clone(),
+        "status" => f.status.clone(),
+        "banner" => f.banner.clone().unwrap_or_default(),
+        "latency" => f.latency_ms.map(|ms| ms.to_string()).unwrap_or_default(),
+        "service" => f.service.clone().unwrap_or_default(),
+        "version" => f.version.clone().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn list(fields: &[&str]) -> String {
+    fields.iter().map(|f| format!("{{{}}}", f)).collect::<Vec<_>>().join(", ")
+}
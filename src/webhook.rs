@@ -0,0 +1,47 @@
+use reqwest::{Client, Url};
+use std::time::Duration;
+
+/// Where --webhook delivers the JSON report once a scan is done
+#[derive(Debug)]
+pub struct Webhook {
+    url: Url,
+    token: Option<String>,
+    client: Client,
+}
+
+impl Webhook {
+    /// Check the URL and set up the client, so a typo fails before scanning
+    /// instead of after
+    pub fn new(url: &str, token: Option<String>, timeout: Duration) -> anyhow::Result<Webhook> {
+        let url = Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid webhook URL {}: {}", url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(anyhow::anyhow!("Webhook URL must be http:// or https://, got {}", url));
+        }
+        // Same crypto provider as --tls-probe; ignored when something already installed one
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let client = Client::builder()
+            .timeout(timeout)
+            .user_agent(concat!("clapscan/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| anyhow::anyhow!("Could not set up the webhook client: {}", e))?;
+        Ok(Webhook { url, token, client })
+    }
+
+    /// POST `body`, a JSON document, and fail unless the server answers 2xx
+    pub async fn send(&self, body: String) -> anyhow::Result<()> {
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("server answered {}", status));
+        }
+        Ok(())
+    }
+}
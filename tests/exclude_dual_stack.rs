@@ -0,0 +1,50 @@
+use clapscan::{exclude_hosts, IpNet};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn scans_ipv4_alone_when_its_ipv6_partner_is_excluded() {
+    let mut hosts = vec![ip("2001:db8::1"), ip("10.0.0.9")];
+    let mut races = HashMap::from([(ip("2001:db8::1"), ip("192.0.2.1"))]);
+    let excluded = [IpNet::parse("2001:db8::/32").unwrap()];
+
+    assert_eq!(exclude_hosts(&mut hosts, &mut races, &excluded), 1);
+    assert_eq!(hosts, [ip("10.0.0.9"), ip("192.0.2.1")]);
+    assert!(races.is_empty());
+}
+
+#[test]
+fn scans_ipv6_alone_when_its_ipv4_partner_is_excluded() {
+    let mut hosts = vec![ip("2001:db8::1")];
+    let mut races = HashMap::from([(ip("2001:db8::1"), ip("192.0.2.1"))]);
+    let excluded = [IpNet::parse("192.0.2.0/24").unwrap()];
+
+    assert_eq!(exclude_hosts(&mut hosts, &mut races, &excluded), 0);
+    assert_eq!(hosts, [ip("2001:db8::1")]);
+    assert!(races.is_empty());
+}
+
+#[test]
+fn drops_a_pair_with_both_sides_excluded() {
+    let mut hosts = vec![ip("2001:db8::1")];
+    let mut races = HashMap::from([(ip("2001:db8::1"), ip("192.0.2.1"))]);
+    let excluded = [IpNet::parse("2001:db8::/32").unwrap(), IpNet::parse("192.0.2.1").unwrap()];
+
+    assert_eq!(exclude_hosts(&mut hosts, &mut races, &excluded), 1);
+    assert!(hosts.is_empty());
+    assert!(races.is_empty());
+}
+
+#[test]
+fn keeps_pairs_without_exclusions() {
+    let mut hosts = vec![ip("2001:db8::1")];
+    let mut races = HashMap::from([(ip("2001:db8::1"), ip("192.0.2.1"))]);
+
+    assert_eq!(exclude_hosts(&mut hosts, &mut races, &[]), 0);
+    assert_eq!(hosts, [ip("2001:db8::1")]);
+    assert_eq!(races.get(&ip("2001:db8::1")), Some(&ip("192.0.2.1")));
+}
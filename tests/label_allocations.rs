@@ -0,0 +1,60 @@
+use clapscan::{scan_remote_each, Finding, Proxy, ScanOptions};
+use indicatif::ProgressBar;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Counts every byte handed out, so the test can see what a scan allocates
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const LABEL_BYTES: usize = 256 * 1024;
+const PORTS: u16 = 64;
+
+#[tokio::test]
+async fn probes_share_the_host_label() {
+    // A proxy that refuses every connect, so each probe ends right away
+    // without touching the network or formatting the target name
+    let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap();
+    let proxy = Proxy::parse(&format!("socks5h://{}", closed)).await.unwrap();
+    let opts = ScanOptions { proxy: Some(proxy), ..ScanOptions::default() };
+    let name = "a".repeat(LABEL_BYTES);
+    let ports: Vec<u16> = (1..=PORTS).collect();
+    let mut findings: Vec<Finding> = Vec::with_capacity(ports.len());
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    scan_remote_each(&name, &ports, &opts, &ProgressBar::hidden(), &mut |f| findings.push(f)).await;
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - before;
+
+    assert_eq!(findings.len(), ports.len());
+    assert!(findings.iter().all(|f| Arc::ptr_eq(&f.host, &findings[0].host)));
+    // A copy of the label per probe would be LABEL_BYTES * PORTS, 16 MiB
+    let per_probe_copies = LABEL_BYTES * PORTS as usize;
+    assert!(
+        allocated < per_probe_copies / 4,
+        "scan allocated {} bytes, per-probe label copies would be {}",
+        allocated,
+        per_probe_copies
+    );
+}
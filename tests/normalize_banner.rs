@@ -0,0 +1,19 @@
+use clapscan::normalize_banner;
+
+#[test]
+fn keeps_dots_that_end_a_line() {
+    assert_eq!(normalize_banner(b"+OK Dovecot ready.\r\n"), "+OK Dovecot ready.");
+    assert_eq!(normalize_banner(b"220 Welcome...\r\n"), "220 Welcome...");
+}
+
+#[test]
+fn splits_lf_only_banners_and_drops_volatile_lines() {
+    let banner = b"HTTP/1.0 200 OK\nDate: Thu, 15 Oct 2026 10:00:00 GMT\nServer: foo\n\n";
+    assert_eq!(normalize_banner(banner), "HTTP/1.0 200 OK | Server: foo");
+}
+
+#[test]
+fn splits_crlf_banners() {
+    let banner = b"HTTP/1.1 200 OK\r\nSet-Cookie: id=1\r\nServer: foo\r\n\r\n";
+    assert_eq!(normalize_banner(banner), "HTTP/1.1 200 OK | Server: foo");
+}
@@ -0,0 +1,47 @@
+use clapscan::{probe_port, PortState, ScanOptions};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::thread;
+use std::time::Duration;
+
+// Accept one connection, write `greeting` and drop the connection right away,
+// with a reset instead of a FIN when `reset` is set
+fn truncating_server(greeting: &'static [u8], reset: bool) -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(greeting).unwrap();
+        if reset {
+            socket2::SockRef::from(&stream).set_linger(Some(Duration::ZERO)).unwrap();
+        }
+    });
+    port
+}
+
+async fn banner_from(port: u16) -> Option<String> {
+    let opts = ScanOptions { banner_timeout: Duration::from_secs(2), ..ScanOptions::default() };
+    match probe_port(IpAddr::V4(Ipv4Addr::LOCALHOST), port, &opts).await {
+        PortState::Open(open) => open.banner,
+        state => panic!("expected an open port, got {}", state.as_str()),
+    }
+}
+
+#[tokio::test]
+async fn keeps_banner_sent_before_close() {
+    let port = truncating_server(b"SSH-2.0-Trunc", false);
+    assert_eq!(banner_from(port).await.as_deref(), Some("SSH-2.0-Trunc"));
+}
+
+#[tokio::test]
+async fn keeps_unfinished_multiline_reply() {
+    // The "220-" line promises more, the connection closes before it comes
+    let port = truncating_server(b"220-mail.example.com ESMTP\r\n220-still", false);
+    assert_eq!(banner_from(port).await.as_deref(), Some("220-mail.example.com ESMTP..220-still"));
+}
+
+#[tokio::test]
+async fn keeps_banner_sent_before_reset() {
+    let port = truncating_server(b"HTTP/1.0 200 OK", true);
+    assert_eq!(banner_from(port).await.as_deref(), Some("HTTP/1.0 200 OK"));
+}